@@ -0,0 +1,75 @@
+use errors::IOError;
+use gen_html::{urldecode, urlencode};
+use images::is_local;
+use posts::{PostTypes, BUNDLE_INDEX};
+use quick_error::ResultExt;
+use regex::{Captures, Regex};
+use std::fs;
+use std::path::Path;
+
+// A bundle's assets are written next to the post's page: `<name>.html` and `<name>/...`
+pub fn bundle_url(name: &str, basepath: &str) -> String {
+    format!("{}/{}", basepath, urlencode(name))
+}
+
+pub fn copy_bundles(posts: &[PostTypes], wwwdir: &Path) -> Result<(), IOError> {
+    for post in posts {
+        if let Some(dir) = post.bundle() {
+            trace!("Copying bundle {:?} for {}", dir, post.name());
+            copy_assets(dir, &wwwdir.join(post.name()), true)?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_assets(src: &Path, target: &Path, top: bool) -> Result<(), IOError> {
+    fs::create_dir_all(target).context(target)?;
+    for entry in fs::read_dir(src).context(src)? {
+        let path = entry.context(src)?.path();
+        let name = match path.file_name() {
+            Some(n) => n.to_owned(),
+            None => continue,
+        };
+        if top && name == BUNDLE_INDEX {
+            continue;
+        }
+        if path.is_dir() {
+            copy_assets(&path, &target.join(&name), false)?;
+        } else {
+            fs::copy(&path, target.join(&name)).context(path.as_path())?;
+        }
+    }
+    Ok(())
+}
+
+lazy_static! {
+    static ref INLINE: Regex = Regex::new(r#"(\]\()(<?)([^)\s>]+)"#).unwrap();
+    static ref REFERENCE: Regex = Regex::new(r#"(?m)^( {0,3}\[[^\]]+\]:[ \t]*)(<?)(\S+?)(>?)(\s|$)"#).unwrap();
+}
+
+// Point relative markdown links/images (inline `[x](file)` and reference `[x]: file`) that name a
+// file in the bundle at its copied location. Anything else is left as written.
+pub fn rewrite_bundle_links(body: &str, bundle: &Path, url: &str) -> String {
+    let rewrite = |target: &str| -> Option<String> {
+        if !is_local(target) || target.starts_with('#') {
+            return None;
+        }
+        let split = target.find(|c| c == '#' || c == '?').unwrap_or(target.len());
+        let (file, suffix) = target.split_at(split);
+        let file = urldecode(file);
+        let file = file.trim_start_matches("./");
+        if file.is_empty() || file.contains("..") || !bundle.join(file).is_file() {
+            return None;
+        }
+        Some(format!("{}/{}{}", url, urlencode(file), suffix))
+    };
+    let body = INLINE.replace_all(body, |caps: &Captures| match rewrite(&caps[3]) {
+        Some(new) => format!("{}{}{}", &caps[1], &caps[2], new),
+        None => caps[0].to_string(),
+    });
+    REFERENCE
+        .replace_all(&body, |caps: &Captures| match rewrite(&caps[3]) {
+            Some(new) => format!("{}{}{}{}{}", &caps[1], &caps[2], new, &caps[4], &caps[5]),
+            None => caps[0].to_string(),
+        }).into_owned()
+}
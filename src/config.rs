@@ -0,0 +1,269 @@
+use errors;
+use errors::IOError::*;
+use quick_error::ResultExt;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use toml;
+
+// Site-wide settings, read from the optional config file (`--config`, default ./redsystem.toml).
+// Every field has a default, so a missing file or section just means the defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub images: ImageConfig,
+    #[serde(default)]
+    pub highlight: HighlightConfig,
+    #[serde(default)]
+    pub markdown: MarkdownConfig,
+    #[serde(default)]
+    pub summary: SummaryConfig,
+    #[serde(default)]
+    pub templates: TemplateConfig,
+    #[serde(default)]
+    pub pagination: PaginationConfig,
+    #[serde(default)]
+    pub related: RelatedConfig,
+    #[serde(default)]
+    pub routes: RouteConfig,
+    #[serde(default)]
+    pub placeholders: PlaceholderConfig,
+}
+
+// Placeholders are the empty posts made up for missing parents (outside of --strict). They're
+// categories unless `type` says otherwise, since all they have is children. With
+// `refuse_near_misses`, a missing parent that looks like a typo of an existing name is an error
+// instead.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct PlaceholderConfig {
+    #[serde(rename = "type")]
+    pub kind: PlaceholderKind,
+    pub template: Option<String>, // instead of the type's usual template
+    pub refuse_near_misses: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum PlaceholderKind {
+    Category,
+    Post,
+}
+
+impl Default for PlaceholderKind {
+    fn default() -> PlaceholderKind {
+        PlaceholderKind::Category
+    }
+}
+
+// Limits on listing routes through the graph, which can otherwise run into the millions on a
+// densely connected site. `max_depth` is in steps down from the index.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RouteConfig {
+    pub max_depth: usize,
+    pub max_routes: usize,
+}
+
+impl Default for RouteConfig {
+    fn default() -> RouteConfig {
+        RouteConfig {
+            max_depth: 16,
+            max_routes: 1000,
+        }
+    }
+}
+
+// Category and index listings are split into pages of `per_page` children (0 keeps everything on
+// one page). Child categories come first; with `categories_first_page_only` they all go on the
+// first page and only the posts are split.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct PaginationConfig {
+    pub per_page: usize,
+    pub categories_first_page_only: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateConfig {
+    // default templates for everything under a dirname, by type, ie
+    // [templates.dirname.Artists] post = "artist.jinja2". Also applies to subdirectories
+    // (Artists/Tokyo) that don't have their own entry for that type.
+    #[serde(default)]
+    pub dirname: HashMap<String, DirnameTemplates>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct DirnameTemplates {
+    pub post: Option<String>,
+    pub category: Option<String>,
+}
+
+impl TemplateConfig {
+    // `pick` chooses the entry for the type in question
+    pub fn for_dirname<F>(&self, dirname: &str, pick: F) -> Option<&str>
+    where
+        F: Fn(&DirnameTemplates) -> &Option<String>,
+    {
+        let mut dir = dirname;
+        loop {
+            if let Some(t) = self.dirname.get(dir).and_then(|d| pick(d).as_ref()) {
+                return Some(t);
+            }
+            match dir.rfind('/') {
+                Some(ix) => dir = &dir[..ix],
+                None => return None,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImageConfig {
+    // widths, in pixels, of the thumbnails generated for every local image
+    #[serde(default = "default_thumbnails")]
+    pub thumbnails: Vec<u32>,
+}
+
+impl Default for ImageConfig {
+    fn default() -> ImageConfig {
+        ImageConfig {
+            thumbnails: default_thumbnails(),
+        }
+    }
+}
+
+fn default_thumbnails() -> Vec<u32> {
+    vec![128, 256]
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HighlightConfig {
+    // one of syntect's built-in theme names
+    #[serde(default = "default_theme")]
+    pub theme: String,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> HighlightConfig {
+        HighlightConfig {
+            theme: default_theme(),
+        }
+    }
+}
+
+fn default_theme() -> String {
+    "InspiredGitHub".to_string()
+}
+
+// How `related` posts are ranked: each other post scores `shared_parent` per parent the two have
+// in common, `link` per [[link]] between them (either way), and `distance` / hops when they're
+// within `max_distance` of each other, ignoring edge direction and the index. The best `count`
+// are kept, ties going by name.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct RelatedConfig {
+    pub count: usize,
+    pub max_distance: usize,
+    pub shared_parent: f64,
+    pub link: f64,
+    pub distance: f64,
+}
+
+impl Default for RelatedConfig {
+    fn default() -> RelatedConfig {
+        RelatedConfig {
+            count: 5,
+            max_distance: 3,
+            shared_parent: 2.0,
+            link: 1.5,
+            distance: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SummaryConfig {
+    // length of a summary taken from the start of the body, when there's no `<!-- more -->`
+    #[serde(default = "default_summary_words")]
+    pub words: usize,
+}
+
+impl Default for SummaryConfig {
+    fn default() -> SummaryConfig {
+        SummaryConfig {
+            words: default_summary_words(),
+        }
+    }
+}
+
+fn default_summary_words() -> usize {
+    40
+}
+
+// The markdown dialect. Posts can override any of these with a `markdown` table in their header.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct MarkdownConfig {
+    pub tables: bool,
+    pub footnotes: bool,
+    pub strikethrough: bool,
+    pub tasklists: bool,
+    pub smart_punctuation: bool,
+    pub heading_anchors: bool,
+}
+
+impl Default for MarkdownConfig {
+    fn default() -> MarkdownConfig {
+        MarkdownConfig {
+            tables: true,
+            footnotes: true,
+            strikethrough: true,
+            tasklists: true,
+            smart_punctuation: false,
+            heading_anchors: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MarkdownOverrides {
+    pub tables: Option<bool>,
+    pub footnotes: Option<bool>,
+    pub strikethrough: Option<bool>,
+    pub tasklists: Option<bool>,
+    pub smart_punctuation: Option<bool>,
+    pub heading_anchors: Option<bool>,
+}
+
+impl MarkdownConfig {
+    pub fn with(&self, o: &MarkdownOverrides) -> MarkdownConfig {
+        MarkdownConfig {
+            tables: o.tables.unwrap_or(self.tables),
+            footnotes: o.footnotes.unwrap_or(self.footnotes),
+            strikethrough: o.strikethrough.unwrap_or(self.strikethrough),
+            tasklists: o.tasklists.unwrap_or(self.tasklists),
+            smart_punctuation: o.smart_punctuation.unwrap_or(self.smart_punctuation),
+            heading_anchors: o.heading_anchors.unwrap_or(self.heading_anchors),
+        }
+    }
+}
+
+pub fn get_config(path: &Path) -> Result<Config, errors::IOError> {
+    if !path.exists() {
+        debug!("No config at {:?}, using defaults", path);
+        return Ok(Config::default());
+    }
+    trace!("Parsing config {:?}", path);
+    let mut f = File::open(path).context(path)?;
+    let mut contents = String::new();
+    f.read_to_string(&mut contents).context(path)?;
+    toml::from_str(&contents).map_err(|e| invalid_config(e, path.to_path_buf()))
+}
@@ -1,4 +1,5 @@
 use std::io;
+use std::num::ParseIntError;
 use std::path::{Path, PathBuf};
 use toml;
 quick_error! {
@@ -24,6 +25,15 @@ quick_error! {
         invalid_header(err: toml::de::Error, file: PathBuf){
             display("The post {:?} has an invalid header: {}", file, err)
         }
+        invalid_config(err: toml::de::Error, file: PathBuf){
+            display("The config {:?} is invalid: {}", file, err)
+        }
+        invalid_theme(theme: String, err: String){
+            display("Can't load highlighting theme {:?}: {}", theme, err)
+        }
+        missing_template(source: String, template: String){
+            display("{} uses the template {:?}, which doesn't exist", source, template)
+        }
         duplicate_name(name: String, post1: PathBuf, post2:PathBuf){
             display("Duplicate names: Post {:?} and {:?} share the name/alias - {}", post1, post2, name)
         }
@@ -33,11 +43,120 @@ quick_error! {
 quick_error! {
     #[derive(Debug)]
     pub enum GraphError {
-        MissingEdgeError(child: String, missing_parents: Vec<String>) {
+        MissingEdgeError(child: String, missing_parents: Vec<(String, Vec<String>)>) {
             description("The given Node is a child of Nodes that do not exist")
-            display(r#"The post {} claims non-existent parents: {:?}"#, child, missing_parents)
+            display(r#"The post {} claims non-existent parents: {}"#, child, missing_parents
+                .iter()
+                .map(|(p, s)| format!("{:?}{}", p, did_you_mean(s)))
+                .collect::<Vec<_>>()
+                .join(", "))
+
+        }
+        UnresolvedLinkError(post: String, target: String, suggestions: Vec<String>) {
+            description("The given Node links to a Node that does not exist")
+            display(r#"The post {} links to non-existent post: [[{}]]{}"#, post, target, did_you_mean(suggestions))
+        }
+        MissingChildError(parent: String, missing_children: Vec<(String, Vec<String>)>) {
+            description("The given Node lists children that do not exist")
+            display(r#"The category {} lists non-existent children: {}"#, parent, missing_children
+                .iter()
+                .map(|(c, s)| format!("{:?}{}", c, did_you_mean(s)))
+                .collect::<Vec<_>>()
+                .join(", "))
+        }
+        ExcludedChildError(parent: String, child: String) {
+            description("The given Node lists a child that refuses it as a parent")
+            display(r#"The category {} lists {} as a child, but {} has it in `not_parent`"#, parent, child, child)
+        }
+
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum LinkError {
+        BrokenLink(post: String, file: PathBuf, link: String) {
+            description("A generated page links to a file that doesn't exist")
+            display(r#"The post {} links to missing file {:?} (in {:?})"#, post, link, file)
+        }
+        OutsideBasepath(post: String, file: PathBuf, link: String) {
+            description("A generated page links to an absolute path outside of the base path")
+            display(r#"The post {} links outside of the base path: {:?} (in {:?})"#, post, link, file)
+        }
+    }
+}
 
+quick_error! {
+    #[derive(Debug)]
+    pub enum ImageError {
+        MissingImage(post: String, path: PathBuf) {
+            description("A post references a local image that doesn't exist")
+            display(r#"The post {} references missing image {:?}"#, post, path)
         }
+        InvalidImage(post: String, path: PathBuf, err: String) {
+            description("A post references a local image that can't be decoded")
+            display(r#"The post {} references invalid image {:?}: {}"#, post, path, err)
+        }
+        OutsideImgdir(post: String, image: String) {
+            description("A post's local image path leaves the image directory")
+            display(r#"The post {} references image {:?}, which isn't allowed to contain `..`"#, post, image)
+        }
+        WriteError(err: io::Error, path: PathBuf) {
+            display("Error writing image {:?}:  {}", path, err)
+            context(path: &'a Path, err: io::Error)
+                -> (err, path.to_path_buf())
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum QueryError {
+        UnknownName(name: String, suggestions: Vec<String>) {
+            description("A query names a post that doesn't exist")
+            display(r#"There is no post named {:?}{}"#, name, did_you_mean(suggestions))
+        }
+        NoEdge(parent: String, child: String) {
+            description("A query asks about a relationship that doesn't exist")
+            display(r#"{} is not a parent of {}"#, parent, child)
+        }
+        InvalidDepth(depth: String, err: ParseIntError) {
+            description("A query's --depth isn't a number")
+            display(r#"--depth {:?}: {}"#, depth, err)
+        }
+    }
+}
+
+// " (did you mean "a" or "b"?)", or nothing without suggestions
+pub fn did_you_mean(suggestions: &[String]) -> String {
+    match suggestions.split_last() {
+        None => String::new(),
+        Some((last, rest)) if rest.is_empty() => format!(" (did you mean {:?}?)", last),
+        Some((last, rest)) => format!(
+            " (did you mean {} or {:?}?)",
+            rest.iter().map(|s| format!("{:?}", s)).collect::<Vec<_>>().join(", "),
+            last
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn missing_parents_suggest_near_misses() {
+        let err = GraphError::MissingEdgeError(
+            "Artists/X".to_string(),
+            vec![
+                ("Gone".to_string(), vec![]),
+                ("Scifi".to_string(), vec!["SciFi".to_string()]),
+                ("Mecah".to_string(), vec!["Mecha".to_string(), "Category/Mecha".to_string()]),
+            ],
+        );
+        assert_eq!(
+            err.to_string(),
+            r#"The post Artists/X claims non-existent parents: "Gone", "Scifi" (did you mean "SciFi"?), "Mecah" (did you mean "Mecha" or "Category/Mecha"?)"#
+        );
     }
 }
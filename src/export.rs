@@ -0,0 +1,186 @@
+use markdown::escape;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use post_graph::{EdgeType, Graph, PostNode};
+use posts::PostTypes;
+use serde_json;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+
+// Formats the graph can be written out in (`--graph-format`).
+pub const FORMATS: &[&str] = &["dot", "json", "graphml", "mermaid"];
+
+// Everything we know about a node, flattened for the exports. `id` is the node's index, which
+// is stable for a given set of posts but not across edits.
+#[derive(Serialize, Debug)]
+struct ExportNode {
+    id: usize,
+    name: String, // full name, ie with its dirname
+    title: String,
+    dirname: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    aliases: Vec<String>,
+    placeholder: bool, // made up for a missing parent
+}
+
+// parent edges point from the parent to the child; link edges from the linking post
+#[derive(Serialize, Debug)]
+struct ExportEdge {
+    source: usize,
+    target: usize,
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+// With `keep`, only those nodes and the edges between them are exported.
+type Keep<'k> = Option<&'k HashSet<NodeIndex>>;
+
+fn nodes(graph: &Graph, keep: Keep) -> Vec<ExportNode> {
+    graph
+        .graph
+        .node_indices()
+        .filter(|ix| keep.map_or(true, |k| k.contains(ix)))
+        .map(|ix| match graph.graph[ix] {
+            PostNode::Node(p) => ExportNode {
+                id: ix.index(),
+                name: p.name(),
+                title: p.title(),
+                dirname: p.dirname().to_string(),
+                kind: match p {
+                    PostTypes::Post(_) => "Post",
+                    PostTypes::Category(_) => "Category",
+                },
+                aliases: p.aliases().clone(),
+                placeholder: p.is_placeholder(),
+            },
+            PostNode::Root() => ExportNode {
+                id: ix.index(),
+                name: "INDEX".to_string(),
+                title: "index".to_string(),
+                dirname: String::new(),
+                kind: "Index",
+                aliases: vec![],
+                placeholder: false,
+            },
+        }).collect()
+}
+
+fn edges(graph: &Graph, keep: Keep) -> Vec<ExportEdge> {
+    graph
+        .graph
+        .edge_references()
+        .filter(|e| keep.map_or(true, |k| k.contains(&e.source()) && k.contains(&e.target())))
+        .map(|e| ExportEdge {
+            source: e.source().index(),
+            target: e.target().index(),
+            kind: match e.weight() {
+                EdgeType::Parent => "parent",
+                EdgeType::Link => "link",
+            },
+        }).collect()
+}
+
+pub fn export(graph: &Graph, format: &str, keep: Keep) -> String {
+    match format {
+        "json" => json(graph, keep),
+        "graphml" => graphml(graph, keep),
+        "mermaid" => mermaid(graph, keep),
+        _ => graph.dot(keep),
+    }
+}
+
+// node-link json, as read by ie networkx's node_link_graph
+fn json(graph: &Graph, keep: Keep) -> String {
+    #[derive(Serialize)]
+    struct NodeLink {
+        directed: bool,
+        multigraph: bool,
+        nodes: Vec<ExportNode>,
+        links: Vec<ExportEdge>,
+    }
+    serde_json::to_string_pretty(&NodeLink {
+        directed: true,
+        multigraph: true, // a post can be both the parent of and linked from another
+        nodes: nodes(graph, keep),
+        links: edges(graph, keep),
+    }).unwrap()
+}
+
+fn graphml(graph: &Graph, keep: Keep) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    for key in &["name", "title", "dirname", "type", "aliases"] {
+        let _ = writeln!(
+            out,
+            "  <key id=\"{0}\" for=\"node\" attr.name=\"{0}\" attr.type=\"string\"/>",
+            key
+        );
+    }
+    out.push_str("  <key id=\"placeholder\" for=\"node\" attr.name=\"placeholder\" attr.type=\"boolean\"/>\n");
+    out.push_str("  <key id=\"edgetype\" for=\"edge\" attr.name=\"type\" attr.type=\"string\"/>\n");
+    out.push_str("  <graph id=\"redsystem\" edgedefault=\"directed\">\n");
+    for n in nodes(graph, keep) {
+        let _ = writeln!(out, "    <node id=\"n{}\">", n.id);
+        for (key, value) in &[
+            ("name", n.name),
+            ("title", n.title),
+            ("dirname", n.dirname),
+            ("type", n.kind.to_string()),
+            ("aliases", n.aliases.join(", ")),
+            ("placeholder", n.placeholder.to_string()),
+        ] {
+            let _ = writeln!(out, "      <data key=\"{}\">{}</data>", key, escape(value));
+        }
+        out.push_str("    </node>\n");
+    }
+    for e in edges(graph, keep) {
+        let _ = writeln!(
+            out,
+            "    <edge source=\"n{}\" target=\"n{}\"><data key=\"edgetype\">{}</data></edge>",
+            e.source, e.target, e.kind
+        );
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+// a mermaid flowchart, with a subgraph per dirname. Placeholders are dashed flags, mermaid's
+// nearest thing to dot's notes.
+fn mermaid(graph: &Graph, keep: Keep) -> String {
+    let label = |n: &ExportNode| {
+        let text = if n.title.is_empty() { &n.name } else { &n.title };
+        format!("\"{}\"", text.replace('"', "#quot;"))
+    };
+    let shape = |n: &ExportNode| match (n.kind, n.placeholder) {
+        ("Index", _) => format!("n{}(({}))", n.id, label(n)),
+        (_, true) => format!("n{}>{}]:::placeholder", n.id, label(n)),
+        ("Category", _) => format!("n{}({})", n.id, label(n)),
+        _ => format!("n{}[{}]", n.id, label(n)),
+    };
+
+    let mut by_dir: BTreeMap<String, Vec<ExportNode>> = BTreeMap::new();
+    for n in nodes(graph, keep) {
+        by_dir.entry(n.dirname.clone()).or_insert_with(Vec::new).push(n);
+    }
+    let mut out = String::from("flowchart TD\n    classDef placeholder stroke-dasharray: 5 5\n");
+    for (i, (dir, nodes)) in by_dir.iter().enumerate() {
+        if dir.is_empty() {
+            nodes.iter().for_each(|n| {
+                let _ = writeln!(out, "    {}", shape(n));
+            });
+        } else {
+            let _ = writeln!(out, "    subgraph d{}[\"{}\"]", i, dir.replace('"', "#quot;"));
+            nodes.iter().for_each(|n| {
+                let _ = writeln!(out, "        {}", shape(n));
+            });
+            out.push_str("    end\n");
+        }
+    }
+    for e in edges(graph, keep) {
+        let arrow = if e.kind == "link" { "-.->" } else { "-->" };
+        let _ = writeln!(out, "    n{} {} n{}", e.source, arrow, e.target);
+    }
+    out
+}
@@ -1,35 +1,77 @@
 use post_graph::Graph;
 
+use bundles::{bundle_url, rewrite_bundle_links};
+use config::{Config, MarkdownConfig, PaginationConfig};
 use errors::IOError;
-use posts::PostTypes;
-use pulldown_cmark::{html, Parser};
+use links::render_wikilinks;
+use markdown::{render_markdown, summarize};
+use posts::{Category, Post, PostTypes};
+use shortcodes::render_shortcodes;
 use quick_error::ResultExt;
 use std;
+use std::cmp;
 use std::collections::HashMap;
 use std::fs::{create_dir_all, File};
 use std::io::prelude::Write;
 use std::iter;
 use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::RwLock;
 use symlink::symlink_file;
 use tera;
 use tera::{from_value, to_value, Context, Tera};
 #[derive(Debug)]
 pub struct PostHtml {
-    filename: String,
-    html: String,
+    pub source: String, // name of the post this was generated from
+    pub filename: String,
+    pub html: String,
 }
 
-pub fn get_templates(templateglob: &str) -> Tera {
+// same encoding as tera's `urlencode` filter (with the default safe "/"),
+// so links built here match the ones the templates build.
+pub fn urlencode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'0'..=b'9' | b'A'..=b'Z' | b'a'..=b'z' | b'-' | b'.' | b'_' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        }).collect()
+}
+
+pub fn urldecode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = ::std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// tera filters are plain fns, so `parsemd` reads the site's [markdown] settings from here
+lazy_static! {
+    static ref PARSEMD_CONFIG: RwLock<MarkdownConfig> = RwLock::new(MarkdownConfig::default());
+}
+
+pub fn get_templates(templateglob: &str, markdown: MarkdownConfig) -> Tera {
+    *PARSEMD_CONFIG.write().unwrap() = markdown;
     let mut tera = compile_templates!(templateglob);
     // and we can add more things to our instance if we want to
     //tera.autoescape_on(vec![".jinja2.html"]);
     tera.register_filter("parsemd", |value, _| {
         let s = try_get_value!("parsemd", "value", String, value);
-        let parser = Parser::new(&s);
-
-        let mut html_buf = String::new();
-        html::push_html(&mut html_buf, parser);
-        Ok(to_value(html_buf).unwrap())
+        let config = *PARSEMD_CONFIG.read().unwrap();
+        Ok(to_value(render_markdown(&s, &config).html).unwrap())
     });
 
     tera.register_function(
@@ -51,72 +93,346 @@ pub fn get_templates(templateglob: &str) -> Tera {
     tera
 }
 
+// the markdown body, with shortcodes rendered, and [[links]] and bundle-relative references
+// turned into real urls
+fn render_body(
+    tera: &Tera,
+    post: &PostTypes,
+    graph: &Graph,
+    basepath: &str,
+) -> Result<String, tera::Error> {
+    let body = render_shortcodes(post.body(), tera, post, basepath)?;
+    let body = render_wikilinks(&body, graph, post.dirname(), basepath);
+    Ok(match post.bundle() {
+        Some(dir) => rewrite_bundle_links(&body, dir, &bundle_url(&post.name(), basepath)),
+        None => body,
+    })
+}
+
+// Renders every post's body up front, so listings of a post (not just its own page) can use its
+// content and summary.
+pub fn render_contents(
+    tera: &Tera,
+    posts: &[PostTypes],
+    graph: &Graph,
+    basepath: &str,
+    config: &Config,
+) -> Result<(), Vec<tera::Error>> {
+    let mut errors = vec![];
+    for post in posts {
+        let body = match render_body(tera, post, graph, basepath) {
+            Ok(b) => b,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        let markdown = config.markdown.with(post.markdown());
+        let rendered = render_markdown(&body, &markdown);
+        post.set_content(rendered.html, rendered.toc);
+        if post.summary().is_empty() {
+            post.set_summary(summarize(&body, &markdown, config.summary.words));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// the post's own template, else its dirname's, else the default for its type
+fn template_for<'a>(post: &'a PostTypes, config: &'a Config) -> &'a str {
+    post.template()
+        .or_else(|| match post {
+            PostTypes::Post(_) => config.templates.for_dirname(post.dirname(), |d| &d.post),
+            PostTypes::Category(_) => config.templates.for_dirname(post.dirname(), |d| &d.category),
+        })
+        .unwrap_or_else(|| match post {
+            PostTypes::Post(_) => "post.jinja2",
+            PostTypes::Category(_) => "category.jinja2",
+        })
+}
+
+// every template named by a post or the config must have been loaded
+pub fn check_templates(
+    tera: &Tera,
+    posts: &[PostTypes],
+    config: &Config,
+) -> Result<(), Vec<IOError>> {
+    let mut errors: Vec<_> = config
+        .templates
+        .dirname
+        .iter()
+        .flat_map(|(d, t)| {
+            vec![("post", &t.post), ("category", &t.category)]
+                .into_iter()
+                .filter_map(move |(kind, t)| t.as_ref().map(|t| (d, kind, t)))
+        }).filter(|&(_, _, t)| !tera.templates.contains_key(t))
+        .map(|(d, kind, t)| {
+            IOError::missing_template(format!("[templates.dirname.{:?}] {}", d, kind), t.to_string())
+        }).collect();
+    if let Some(ref t) = config.placeholders.template {
+        if !tera.templates.contains_key(t) {
+            errors.push(IOError::missing_template("[placeholders]".to_string(), t.to_string()));
+        }
+    }
+    // then the template each page will actually be rendered with. Placeholders weren't read from
+    // a file, so they're named instead, and the ones given the [placeholders] template were
+    // covered above.
+    errors.extend(
+        posts
+            .iter()
+            .filter(|p| !(p.is_placeholder() && p.template().is_some()))
+            .filter(|p| !tera.templates.contains_key(template_for(p, config)))
+            .map(|p| {
+                let source = if p.is_placeholder() {
+                    format!("The placeholder {:?}", p.name())
+                } else {
+                    format!("{:?}", p.file())
+                };
+                IOError::missing_template(source, template_for(p, config).to_string())
+            }),
+    );
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Paginator {
+    pub current: usize, // 1-based
+    pub total: usize,
+    pub first: String,
+    pub last: String,
+    pub prev: Option<String>,
+    pub next: Option<String>,
+}
+
+// The first page of a listing keeps the plain filename (SciFi.html, index.html); later ones go
+// right next to it (SciFi.page2.html, index.page2.html), never in a directory of the same name,
+// which could be a page bundle's.
+fn page_filename(name: Option<&str>, page: usize) -> String {
+    match (name, page) {
+        (Some(name), 1) => format!("{}.html", name),
+        (Some(name), n) => format!("{}.page{}.html", name, n),
+        (None, 1) => "index.html".to_string(),
+        (None, n) => format!("index.page{}.html", n),
+    }
+}
+
+// Posts of a transitive listing, grouped by the category they were found in (None for the
+// listing's own children). Only consecutive posts are grouped, so each page gets its own groups.
+#[derive(Serialize, Debug)]
+pub struct PostGroup<'a> {
+    pub category: Option<&'a Category>,
+    pub posts: Vec<&'a Post>,
+}
+
+// Splits a listing's children into pages, categories first. Always gives at least one page.
+fn paginate<'a, T: Clone>(
+    cats: Vec<&'a Category>,
+    posts: Vec<T>,
+    config: &PaginationConfig,
+) -> Vec<(Vec<&'a Category>, Vec<T>)> {
+    let n = config.per_page;
+    if n == 0 {
+        return vec![(cats, posts)];
+    }
+    if config.categories_first_page_only {
+        let mut pages: Vec<_> = posts.chunks(n).map(|p| (vec![], p.to_vec())).collect();
+        if pages.is_empty() {
+            pages.push((vec![], vec![]));
+        }
+        pages[0].0 = cats;
+        return pages;
+    }
+    let (c, total) = (cats.len(), cats.len() + posts.len());
+    let count = cmp::max(1, (total + n - 1) / n);
+    (0..count)
+        .map(|i| {
+            let (start, end) = (i * n, cmp::min((i + 1) * n, total));
+            (
+                cats[cmp::min(start, c)..cmp::min(end, c)].to_vec(),
+                posts[start.saturating_sub(c)..end.saturating_sub(c)].to_vec(),
+            )
+        }).collect()
+}
+
+// Renders a category or the index, one page at a time; each page gets its slice of `childcats`
+// and `childposts`, and a `paginator`. Transitive listings also get their posts as `groups`.
+fn gen_listing(
+    tera: &Tera,
+    template: &str,
+    ctx: &Context,
+    name: Option<&str>,
+    (cats, posts): (Vec<&Category>, Vec<(Option<&Category>, &Post)>),
+    grouped: bool,
+    basepath: &str,
+    config: &Config,
+) -> Result<Vec<PostHtml>, tera::Error> {
+    let pages = paginate(cats, posts, &config.pagination);
+    let total = pages.len();
+    let url = |n| format!("{}/{}", basepath, urlencode(&page_filename(name, n)));
+    pages
+        .into_iter()
+        .enumerate()
+        .map(|(i, (cats, posts))| {
+            let current = i + 1;
+            let mut ctx = ctx.clone();
+            ctx.insert("childcats", &cats);
+            ctx.insert("childposts", &posts.iter().map(|&(_, p)| p).collect::<Vec<_>>());
+            if grouped {
+                ctx.insert("groups", &group_posts(&posts));
+            }
+            ctx.insert(
+                "paginator",
+                &Paginator {
+                    current,
+                    total,
+                    first: url(1),
+                    last: url(total),
+                    prev: if current > 1 { Some(url(current - 1)) } else { None },
+                    next: if current < total { Some(url(current + 1)) } else { None },
+                },
+            );
+            Ok(PostHtml {
+                source: name.unwrap_or("INDEX").to_string(),
+                filename: page_filename(name, current),
+                html: tera.render(template, &ctx)?,
+            })
+        }).collect()
+}
+
+fn group_posts<'a>(posts: &[(Option<&'a Category>, &'a Post)]) -> Vec<PostGroup<'a>> {
+    let mut groups: Vec<PostGroup> = vec![];
+    for &(cat, post) in posts {
+        let same = match (groups.last().map(|g| g.category), cat) {
+            (Some(Some(a)), Some(b)) => ptr::eq(a, b),
+            (Some(None), None) => true,
+            _ => false,
+        };
+        if same {
+            groups.last_mut().unwrap().posts.push(post);
+        } else {
+            groups.push(PostGroup {
+                category: cat,
+                posts: vec![post],
+            });
+        }
+    }
+    groups
+}
+
 fn gen_post(
     tera: &Tera,
     post: &PostTypes,
     graph: &Graph,
     basepath: &str,
-) -> Result<PostHtml, tera::Error> {
+    config: &Config,
+) -> Result<Vec<PostHtml>, tera::Error> {
     let mut ctx = Context::new();
 
     ctx.insert("title", &post.title());
     ctx.insert("basepath", &basepath);
-    let html = match post {
+    match post {
         PostTypes::Post(p) => {
             ctx.insert("post", &p);
             ctx.insert("children", &p.children);
-            tera.render("post.jinja2", &ctx)
+            Ok(vec![PostHtml {
+                source: post.name(),
+                filename: format!("{}.html", post.name()),
+                html: tera.render(template_for(post, config), &ctx)?,
+            }])
         }
         PostTypes::Category(c) => {
             //ctx.insert("category", c);
             ctx.insert("cat", c);
-            ctx.insert(
-                "childcats",
-                &graph.get_child_cats(*graph.getidx(&post.name())),
-            );
-            ctx.insert(
-                "childposts",
-                &graph.get_child_posts(*graph.getidx(&post.name())),
-            );
-            tera.render("category.jinja2", &ctx)
+            let idx = *graph.getidx(&post.name());
+            let posts = if c.transitive {
+                graph.get_descendant_posts(idx, c.max_depth)
+            } else {
+                graph.get_child_posts(idx).into_iter().map(|p| (None, p)).collect()
+            };
+            gen_listing(
+                tera,
+                template_for(post, config),
+                &ctx,
+                Some(&post.name()),
+                (graph.get_child_cats(idx), posts),
+                c.transitive,
+                basepath,
+                config,
+            )
         }
-    };
-    match html {
-        Ok(s) => Ok(PostHtml {
-            filename: format!("{}.html", post.name()),
-            html: s,
-        }),
-        Err(e) => Err(e),
     }
 }
-fn gen_root(tera: &Tera, graph: &Graph, basepath: &str) -> Result<PostHtml, tera::Error> {
+fn gen_root(
+    tera: &Tera,
+    graph: &Graph,
+    basepath: &str,
+    config: &Config,
+) -> Result<Vec<PostHtml>, tera::Error> {
     let mut ctx = Context::new();
-    ctx.insert("childcats", &graph.get_child_cats(graph.root));
-    ctx.insert("childposts", &graph.get_child_posts(graph.root));
     ctx.insert("basepath", &basepath);
-    let html = tera.render("index.jinja2", &ctx);
-    match html {
-        Ok(s) => Ok(PostHtml {
-            filename: "index.html".to_string(),
-            html: s,
-        }),
-        Err(e) => Err(e),
-    }
+    gen_listing(
+        tera,
+        "index.jinja2",
+        &ctx,
+        None,
+        (
+            graph.get_child_cats(graph.root),
+            graph.get_child_posts(graph.root).into_iter().map(|p| (None, p)).collect(),
+        ),
+        false,
+        basepath,
+        config,
+    )
+}
+// the whole graph as an svg, inlined into sitemap.jinja2
+pub fn gen_sitemap(tera: &Tera, svg: &str, basepath: &str) -> Result<PostHtml, tera::Error> {
+    let mut ctx = Context::new();
+    ctx.insert("basepath", &basepath);
+    ctx.insert("svg", &svg);
+    Ok(PostHtml {
+        source: "INDEX".to_string(),
+        filename: "sitemap.html".to_string(),
+        html: tera.render("sitemap.jinja2", &ctx)?,
+    })
 }
+
+// Every placeholder, with the posts that named it as a parent (its children): the pages still to
+// be written.
+pub fn gen_stubs(tera: &Tera, posts: &[PostTypes], basepath: &str) -> Result<PostHtml, tera::Error> {
+    let mut stubs: Vec<_> = posts.iter().filter(|p| p.is_placeholder()).collect();
+    stubs.sort_unstable_by_key(|p| p.name());
+    let mut ctx = Context::new();
+    ctx.insert("basepath", &basepath);
+    ctx.insert("stubs", &stubs);
+    Ok(PostHtml {
+        source: "INDEX".to_string(),
+        filename: "stubs.html".to_string(),
+        html: tera.render("stubs.jinja2", &ctx)?,
+    })
+}
+
 pub fn gen_posts_html(
     tera: &Tera,
     posts: &[PostTypes],
     graph: &Graph,
     basepath: &str,
+    config: &Config,
 ) -> Result<Vec<PostHtml>, Vec<tera::Error>> {
-    let (posts, errors): (Vec<_>, Vec<_>) = posts
+    let (pages, errors): (Vec<_>, Vec<_>) = posts
         .iter()
-        .map(|p| gen_post(tera, p, graph, basepath))
-        .chain(iter::once(gen_root(tera, graph, basepath))) // inject the index node
+        .map(|p| gen_post(tera, p, graph, basepath, config))
+        .chain(iter::once(gen_root(tera, graph, basepath, config))) // inject the index node
         .partition(Result::is_ok);
 
     if errors.is_empty() {
-        Ok(posts.into_iter().map(Result::unwrap).collect())
+        Ok(pages.into_iter().flat_map(Result::unwrap).collect())
     } else {
         Err(errors.into_iter().map(Result::unwrap_err).collect())
     }
@@ -159,44 +475,3 @@ pub fn create_symlinks(wwwdir: &Path, srcdir: &Path, posts: &[PostHtml]) -> Resu
         }).collect()
     //Ok(())
 }
-// pub fn create_symlinks(wwwdir: &Path, srcdir: &Path, graph: &Graph) -> Result<(), IOError> {
-//     // generate symlinks and directories to each post as necessary
-//     //
-//     let paths = graph.find_all_paths();
-//     debug!("Creating {} symlinks", paths.len());
-//     for (target, path, m_dupix) in paths {
-//         info!("{}, {:?}, {:?}",target, path, m_dupix);
-//         let targetname = format!("{}.html", target);
-//         let targetdir: PathBuf = wwwdir.join(path.iter().collect::<PathBuf>());
-//         let targetfile = targetdir.join(&targetname);
-//         let srcfile = path
-//             .iter()
-//             // if this is a cycle (in which case, m_dupix exists), then move back up to the previous symlink-copy.
-//             // Else, we need to point to real html file, by going all the way back up to the root (www/).
-//             .skip(m_dupix.unwrap_or(0))
-//             // we need to do this with relative paths, because the whole directory will likely be moved later.
-//             .map(|_| "../")
-//             .collect::<PathBuf>()
-//             .join("../")
-//             .join(&srcdir)
-//             .join(&targetname);
-
-//         // then create the symlink: targetpath -> sourcefile
-//         trace!(
-//             "Creating symlink -- file: {:?} -> original:{:?}",
-//             targetfile,
-//             srcfile
-//         );
-//         //let srcfile = srcdir.join(target);
-
-//         // now we need to create the relevant directories (targetdir)
-//         // ignore any pre-existing directories; overlapping creation is fine.
-//         if let Err(e) = create_dir_all(&targetdir) {
-//             if e.kind() != std::io::ErrorKind::AlreadyExists {
-//                 return Err(e).context(&targetdir)?;
-//             }
-//         }
-//         symlink_file(&srcfile, &targetfile).context(&srcfile)?;
-//     }
-//     Ok(())
-// }
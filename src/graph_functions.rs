@@ -0,0 +1,181 @@
+use post_graph::{breadth_first, shortest_chain, Graph, PostNode};
+use sitemap;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tera;
+use tera::{from_value, to_value, Map, Tera, Value};
+
+// Tera functions have to own their data, so they work off a copy of the graph's Parent edges,
+// taken when they're added. Nodes are the serialized posts (without their html content
+// or toc), plus their `url`; the index node is `{type: "Index", name: "INDEX", ...}`. Those are
+// taken again once the bodies are rendered (see `GraphFunctions::refresh`).
+struct Snapshot {
+    nodes: RwLock<Vec<Value>>,
+    keys: Vec<String>, // full name of each node
+    children: Vec<Vec<usize>>, // sorted by name, so every traversal is deterministic
+    parents: Vec<Vec<usize>>,
+    names: HashMap<String, usize>, // every name and alias
+    map: Arc<sitemap::Map>,
+}
+
+fn node_values(graph: &Graph, basepath: &str) -> Vec<Value> {
+    let g = &graph.graph;
+    g.node_indices()
+        .map(|ix| {
+            let mut value = match g[ix] {
+                PostNode::Node(p) => to_value(p).unwrap(),
+                PostNode::Root() => {
+                    let mut root = Map::new();
+                    root.insert("type".to_string(), Value::String("Index".to_string()));
+                    root.insert("name".to_string(), Value::String("INDEX".to_string()));
+                    root.insert("title".to_string(), Value::String("index".to_string()));
+                    Value::Object(root)
+                }
+            };
+            if let Value::Object(ref mut map) = value {
+                map.remove("content");
+                map.remove("toc");
+                map.insert("url".to_string(), Value::String(graph.url_for(ix, basepath)));
+            }
+            value
+        }).collect()
+}
+
+impl Snapshot {
+    fn new(graph: &Graph, map: Arc<sitemap::Map>, basepath: &str) -> Snapshot {
+        let g = &graph.graph;
+        let node_name = |ix| match g[ix] {
+            PostNode::Node(p) => p.name(),
+            PostNode::Root() => "INDEX".to_string(),
+        };
+        let sorted = |mut ixs: Vec<_>| {
+            ixs.sort_unstable_by_key(|&ix| node_name(ix));
+            ixs.dedup();
+            ixs.into_iter().map(|ix: ::petgraph::graph::NodeIndex| ix.index()).collect()
+        };
+        let mut names: HashMap<_, _> = graph
+            .names()
+            .map(|(name, ix)| (name.to_string(), ix.index()))
+            .collect();
+        names.insert("INDEX".to_string(), graph.root.index());
+        Snapshot {
+            nodes: RwLock::new(node_values(graph, basepath)),
+            keys: g.node_indices().map(node_name).collect(),
+            children: g.node_indices().map(|ix| sorted(graph.children(ix))).collect(),
+            parents: g.node_indices().map(|ix| sorted(graph.parents(ix))).collect(),
+            names,
+            map,
+        }
+    }
+
+    fn lookup(&self, function: &str, args: &HashMap<String, Value>, key: &str) -> tera::Result<usize> {
+        let name = match args.get(key).map(|v| from_value::<String>(v.clone())) {
+            Some(Ok(name)) => name,
+            Some(Err(_)) => return Err(format!("{}: `{}` must be a string", function, key).into()),
+            None => return Err(format!("{}: missing argument `{}`", function, key).into()),
+        };
+        self.names
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| format!("{}: no post named {:?}", function, name).into())
+    }
+
+    fn values(&self, ixs: &[usize]) -> Value {
+        let nodes = self.nodes.read().unwrap();
+        Value::Array(ixs.iter().map(|&ix| nodes[ix].clone()).collect())
+    }
+}
+
+// The functions' own copy of the graph, to bring up to date once the posts are rendered.
+pub struct GraphFunctions(Arc<Snapshot>);
+
+impl GraphFunctions {
+    // picks up what rendering added to the posts, ie summaries derived from their bodies
+    pub fn refresh(&self, graph: &Graph, basepath: &str) {
+        *self.0.nodes.write().unwrap() = node_values(graph, basepath);
+    }
+}
+
+pub fn add_graph_functions(
+    tera: &mut Tera,
+    graph: &Graph,
+    map: Arc<sitemap::Map>,
+    basepath: &str,
+) -> GraphFunctions {
+    let snapshot = Arc::new(Snapshot::new(graph, map, basepath));
+
+    let s = snapshot.clone();
+    tera.register_function(
+        "get_node",
+        Box::new(move |args| -> tera::Result<Value> {
+            let ix = s.lookup("get_node", &args, "name")?;
+            Ok(s.nodes.read().unwrap()[ix].clone())
+        }),
+    );
+
+    let s = snapshot.clone();
+    tera.register_function(
+        "descendants",
+        Box::new(move |args| -> tera::Result<Value> {
+            let ix = s.lookup("descendants", &args, "name")?;
+            let depth = match args.get("depth").map(|v| from_value::<usize>(v.clone())) {
+                Some(Ok(d)) => Some(d),
+                Some(Err(_)) => return Err("descendants: `depth` must be a positive integer".into()),
+                None => None,
+            };
+            Ok(s.values(&breadth_first(ix, depth, |n| s.children[n].clone())))
+        }),
+    );
+
+    let s = snapshot.clone();
+    tera.register_function(
+        "ancestors",
+        Box::new(move |args| -> tera::Result<Value> {
+            let ix = s.lookup("ancestors", &args, "name")?;
+            Ok(s.values(&breadth_first(ix, None, |n| s.parents[n].clone())))
+        }),
+    );
+
+    let s = snapshot.clone();
+    tera.register_function(
+        "siblings",
+        Box::new(move |args| -> tera::Result<Value> {
+            let ix = s.lookup("siblings", &args, "name")?;
+            let mut siblings: Vec<_> = s.parents[ix]
+                .iter()
+                .flat_map(|&p| s.children[p].iter().cloned())
+                .filter(|&c| c != ix)
+                .collect();
+            siblings.sort_unstable_by(|&a, &b| (&s.keys[a], a).cmp(&(&s.keys[b], b)));
+            siblings.dedup();
+            Ok(s.values(&siblings))
+        }),
+    );
+
+    let s = snapshot.clone();
+    tera.register_function(
+        "minimap",
+        Box::new(move |args| -> tera::Result<Value> {
+            let ix = s.lookup("minimap", &args, "name")?;
+            let depth = match args.get("depth").map(|v| from_value::<usize>(v.clone())) {
+                Some(Ok(d)) => d,
+                Some(Err(_)) => return Err("minimap: `depth` must be a positive integer".into()),
+                None => 1,
+            };
+            Ok(Value::String(s.map.neighbourhood(ix, depth).to_svg(Some(0))))
+        }),
+    );
+
+    let s = snapshot.clone();
+    tera.register_function(
+        "path",
+        Box::new(move |args| -> tera::Result<Value> {
+            let from = s.lookup("path", &args, "from")?;
+            let to = s.lookup("path", &args, "to")?;
+            let path = shortest_chain(from, to, |n| s.children[n].clone());
+            Ok(s.values(&path.unwrap_or_default()))
+        }),
+    );
+
+    GraphFunctions(snapshot)
+}
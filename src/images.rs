@@ -0,0 +1,125 @@
+use config::ImageConfig;
+use errors::ImageError;
+use image;
+use image::GenericImageView;
+use posts::{PostTypes, TeraImage};
+use quick_error::ResultExt;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// anything with a scheme (http:, data:, ...), a protocol-relative url, or a path from the site's
+// root (already published, ie `/img/shot.png`) is left alone
+pub fn is_local(image: &str) -> bool {
+    !(image.is_empty() || image.starts_with('/') || image.contains(':'))
+}
+
+// Copies local images into `<outdir>/img` under content-hashed names, generating the configured
+// thumbnails alongside. Each source file is only processed once, however many posts use it.
+pub struct ImageProcessor<'c> {
+    outdir: PathBuf,
+    urlbase: String,
+    config: &'c ImageConfig,
+    done: HashMap<PathBuf, TeraImage>,
+}
+
+impl<'c> ImageProcessor<'c> {
+    pub fn new(wwwdir: &Path, basepath: &str, config: &'c ImageConfig) -> ImageProcessor<'c> {
+        ImageProcessor {
+            outdir: wwwdir.join("img"),
+            urlbase: format!("{}/img", basepath),
+            config,
+            done: HashMap::new(),
+        }
+    }
+
+    pub fn process(&mut self, post: &str, src: &Path) -> Result<TeraImage, ImageError> {
+        if let Some(img) = self.done.get(src) {
+            return Ok(img.clone());
+        }
+        if !src.is_file() {
+            return Err(ImageError::MissingImage(post.to_string(), src.to_path_buf()));
+        }
+        let invalid = |e: image::ImageError| {
+            ImageError::InvalidImage(post.to_string(), src.to_path_buf(), e.to_string())
+        };
+        let bytes = fs::read(src).context(src)?;
+        let decoded = image::load_from_memory(&bytes).map_err(&invalid)?;
+
+        let stem = src.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+        let ext = src.extension().and_then(|s| s.to_str()).unwrap_or("");
+        let hashed = format!("{}.{:016x}", stem, fnv1a(&bytes));
+
+        fs::create_dir_all(&self.outdir).context(self.outdir.as_path())?;
+        let filename = format!("{}.{}", hashed, ext);
+        let target = self.outdir.join(&filename);
+        fs::write(&target, &bytes).context(target.as_path())?;
+        trace!("Copied image {:?} to {:?}", src, target);
+
+        let (width, height) = decoded.dimensions();
+        let mut thumbnails = vec![];
+        let mut sizes = self.config.thumbnails.clone();
+        sizes.sort_unstable();
+        sizes.dedup();
+        // never upscale; anything at least as wide as the original just uses the original
+        for w in sizes.into_iter().filter(|&w| w > 0 && w < width) {
+            let thumb = decoded.resize(w, u32::max_value(), image::imageops::FilterType::Triangle);
+            let filename = format!("{}.{}w.{}", hashed, w, ext);
+            let target = self.outdir.join(&filename);
+            thumb.save(&target).map_err(&invalid)?;
+            trace!("Created thumbnail {:?}", target);
+            thumbnails.push(TeraImage {
+                url: format!("{}/{}", self.urlbase, filename),
+                width: thumb.width(),
+                height: thumb.height(),
+                thumbnails: vec![],
+            });
+        }
+
+        let img = TeraImage {
+            url: format!("{}/{}", self.urlbase, filename),
+            width,
+            height,
+            thumbnails,
+        };
+        self.done.insert(src.to_path_buf(), img.clone());
+        Ok(img)
+    }
+}
+
+// 64-bit FNV-1a; unlike std's hashers its output is fixed, so names survive toolchain upgrades
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+// resolve every post's local `image` against its bundle (if it has one and the file is there)
+// or imgdir, and attach the processed result to the post
+pub fn process_images(
+    posts: &[PostTypes],
+    imgdir: &Path,
+    processor: &mut ImageProcessor,
+) -> Result<(), Vec<ImageError>> {
+    let mut errors = vec![];
+    for post in posts.iter().filter(|p| is_local(p.image())) {
+        // local images are read from imgdir (or the bundle), never from anywhere above it
+        if Path::new(post.image()).components().any(|c| c == Component::ParentDir) {
+            errors.push(ImageError::OutsideImgdir(post.name(), post.image().to_string()));
+            continue;
+        }
+        let src = match post.bundle() {
+            Some(dir) if dir.join(post.image()).is_file() => dir.join(post.image()),
+            _ => imgdir.join(post.image()),
+        };
+        match processor.process(&post.name(), &src) {
+            Ok(img) => post.set_image(img),
+            Err(e) => errors.push(e),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,188 @@
+use errors::LinkError;
+use gen_html::{urldecode, PostHtml};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    // only real tags; escaped text like `&lt;a href=...` never starts with `<`
+    static ref TAG: Regex = Regex::new(r"<[a-zA-Z][^>]*>").unwrap();
+    // every attribute of a tag, whole, so one's value is never mistaken for another attribute
+    static ref ATTR: Regex =
+        Regex::new(r#"\s([^\s=/>]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>'"]+)))?"#).unwrap();
+}
+
+// Verifies every internal href/src in the generated pages against the files in the outdir.
+// External links (anything with a scheme, or protocol-relative) and bare fragments are skipped.
+pub fn check_output(wwwdir: &Path, pages: &[PostHtml], basepath: &str) -> Result<(), Vec<LinkError>> {
+    let mut errors = vec![];
+    for page in pages {
+        let pagefile = PathBuf::from(&page.filename);
+        let attrs = TAG
+            .find_iter(&page.html)
+            .flat_map(|tag| ATTR.captures_iter(tag.as_str()))
+            .filter(|cap| {
+                let name = cap[1].to_ascii_lowercase();
+                name == "href" || name == "src"
+            });
+        for cap in attrs {
+            let link = match cap.get(2).or_else(|| cap.get(3)).or_else(|| cap.get(4)) {
+                Some(m) => m.as_str(),
+                None => continue,
+            };
+            if is_external(link) {
+                continue;
+            }
+            let target = match resolve(&pagefile, link, basepath) {
+                Some(t) => t,
+                None => {
+                    errors.push(LinkError::OutsideBasepath(
+                        page.source.clone(),
+                        pagefile.clone(),
+                        link.to_string(),
+                    ));
+                    continue;
+                }
+            };
+            let target = wwwdir.join(target);
+            let found = if target.is_dir() {
+                target.join("index.html").is_file()
+            } else {
+                target.is_file()
+            };
+            if !found {
+                errors.push(LinkError::BrokenLink(
+                    page.source.clone(),
+                    pagefile.clone(),
+                    link.to_string(),
+                ));
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn is_external(link: &str) -> bool {
+    if link.is_empty() || link.starts_with('#') || link.starts_with("//") {
+        return true;
+    }
+    // a scheme is letters/digits/+-. followed by ':', before any '/'
+    match link.find(':') {
+        Some(colon) => {
+            let scheme = &link[..colon];
+            !scheme.is_empty()
+                && scheme.chars().next().unwrap().is_ascii_alphabetic()
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+        }
+        None => false,
+    }
+}
+
+// turn a link into a path relative to the outdir.
+// None if an absolute link doesn't start with the basepath.
+fn resolve(pagefile: &Path, link: &str, basepath: &str) -> Option<PathBuf> {
+    let link = link.split(|c| c == '#' || c == '?').next().unwrap_or("");
+    let link = urldecode(link);
+    let (base, rest) = if link.starts_with('/') {
+        let rest = if basepath.is_empty() {
+            &link[..]
+        } else if link == basepath || link.starts_with(&format!("{}/", basepath)) {
+            &link[basepath.len()..]
+        } else {
+            return None;
+        };
+        (PathBuf::new(), rest.trim_start_matches('/').to_string())
+    } else {
+        let dir = pagefile.parent().map(Path::to_path_buf).unwrap_or_default();
+        (dir, link.clone())
+    };
+
+    let mut out = base;
+    for part in rest.split('/') {
+        match part {
+            "" | "." => (),
+            // like browsers, going above the root just stays at the root
+            ".." => {
+                out.pop();
+            }
+            p => out.push(p),
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use gen_html::{create_posts, gen_posts_html, gen_sitemap, gen_stubs, get_templates, render_contents};
+    use graph_functions::add_graph_functions;
+    use markdown;
+    use post_graph::Graph;
+    use posts::{Category, Post, PostTypes};
+    use sitemap;
+    use std::sync::Arc;
+    use std::{env, fs, process};
+
+    #[test]
+    fn default_templates_only_link_to_written_files() {
+        let posts = vec![
+            PostTypes::Category(Category {
+                name: "Science Fiction".to_string(),
+                title: "Science Fiction".to_string(),
+                ..Default::default()
+            }),
+            PostTypes::Post(Post {
+                name: "Top".to_string(),
+                title: "Top".to_string(),
+                ..Default::default()
+            }),
+            PostTypes::Post(Post {
+                name: "Mecha".to_string(),
+                title: "Mecha".to_string(),
+                dirname: "Category".to_string(),
+                parents: vec!["/Science Fiction".to_string(), "INDEX".to_string()],
+                ..Default::default()
+            }),
+        ];
+        let config = Config::default();
+        let mut graph = Graph::new();
+        graph.add_posts(&posts);
+        graph.add_edges(&posts).unwrap();
+        graph.add_links(&posts);
+        for post in &posts {
+            post.set_children_names(graph.get_children_names(&post));
+            post.set_parent_names(graph.get_parent_names(&post));
+            post.set_backlinks(graph.get_backlink_names(&post));
+            post.set_related(graph.get_related_names(&post, &config.related));
+        }
+
+        let mut tera = get_templates(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/jinja2/*"), config.markdown);
+        let map = Arc::new(sitemap::Map::from_graph(&graph, ""));
+        let functions = add_graph_functions(&mut tera, &graph, map.clone(), "");
+        render_contents(&tera, &posts, &graph, "", &config).unwrap();
+        functions.refresh(&graph, "");
+        let mut pages = gen_posts_html(&tera, &posts, &graph, "", &config).unwrap();
+        let svg = map.to_svg(None);
+        pages.push(gen_sitemap(&tera, &svg, "").unwrap());
+        pages.push(gen_stubs(&tera, &posts, "").unwrap());
+
+        // what main writes besides the pages
+        let wwwdir = env::temp_dir().join(format!("redsystem-linkcheck-{}", process::id()));
+        fs::create_dir_all(wwwdir.join("css")).unwrap();
+        fs::write(wwwdir.join("css/style.css"), "").unwrap();
+        markdown::write_highlight_css(&wwwdir.join("css"), &config.highlight.theme).unwrap();
+        sitemap::write_svg(&wwwdir, &svg).unwrap();
+        create_posts(&wwwdir, &pages).unwrap();
+
+        let checked = check_output(&wwwdir, &pages, "");
+        fs::remove_dir_all(&wwwdir).unwrap();
+        let broken: Vec<_> = checked.err().unwrap_or_default().iter().map(|e| e.to_string()).collect();
+        assert!(broken.is_empty(), "{:#?}", broken);
+    }
+}
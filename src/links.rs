@@ -0,0 +1,82 @@
+use gen_html::urlencode;
+use post_graph::Graph;
+use pulldown_cmark::{Event, Parser, Tag};
+use std::ops::Range;
+
+// A `[[target]]` or `[[target|label]]` reference found in a post body.
+// start/end are byte offsets of the whole `[[...]]` span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WikiLink {
+    pub target: String,
+    pub label: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Links inside code spans and code blocks are left alone, as code.
+pub fn find_wikilinks(body: &str) -> Vec<WikiLink> {
+    let code = code_ranges(body);
+    let mut out = vec![];
+    let mut offset = 0;
+    while let Some(open) = body[offset..].find("[[") {
+        let start = offset + open;
+        let inner_start = start + 2;
+        let close = match body[inner_start..].find("]]") {
+            Some(c) => inner_start + c,
+            None => break,
+        };
+        let inner = &body[inner_start..close];
+        // a newline or a nested opener means this wasn't a link after all
+        if inner.contains('\n') || inner.contains("[[") {
+            offset = inner_start;
+            continue;
+        }
+        let (target, label) = match inner.find('|') {
+            Some(bar) => (inner[..bar].trim(), inner[bar + 1..].trim()),
+            None => (inner.trim(), inner.trim()),
+        };
+        let in_code = code.iter().any(|r| r.start <= start && start < r.end);
+        if !target.is_empty() && !in_code {
+            out.push(WikiLink {
+                target: target.to_string(),
+                label: label.to_string(),
+                start,
+                end: close + 2,
+            });
+        }
+        offset = close + 2;
+    }
+    out
+}
+
+// byte ranges of the body's inline code and code blocks
+pub fn code_ranges(body: &str) -> Vec<Range<usize>> {
+    Parser::new(body)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Code(_) | Event::Start(Tag::CodeBlock(_)) => Some(range),
+            _ => None,
+        }).collect()
+}
+
+// Rewrite every wiki link into a standard markdown link, pointing at the page of the node it
+// resolves to, as seen from `dir` (the post's dirname). Unresolved links are reduced to their
+// label; they're reported before rendering.
+pub fn render_wikilinks(body: &str, graph: &Graph, dir: &str, basepath: &str) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut last = 0;
+    for link in find_wikilinks(body) {
+        out.push_str(&body[last..link.start]);
+        match graph.resolve_from(&link.target, dir) {
+            Some(ix) => out.push_str(&format!("[{}]({})", link.label, graph.url_for(ix, basepath))),
+            None => out.push_str(&link.label),
+        }
+        last = link.end;
+    }
+    out.push_str(&body[last..]);
+    out
+}
+
+pub fn node_url(name: &str, basepath: &str) -> String {
+    format!("{}/{}.html", basepath, urlencode(name))
+}
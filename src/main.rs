@@ -6,6 +6,8 @@ extern crate serde_derive;
 extern crate pulldown_cmark;
 extern crate serde;
 #[macro_use]
+extern crate serde_json;
+#[macro_use]
 extern crate tera;
 extern crate indicatif;
 extern crate symlink;
@@ -14,27 +16,51 @@ extern crate toml;
 extern crate log;
 extern crate clap;
 extern crate dialoguer;
+extern crate image;
+#[macro_use]
+extern crate lazy_static;
+extern crate regex;
 extern crate iron;
 extern crate mount;
 extern crate simplelog;
 extern crate staticfile;
+extern crate strsim;
+extern crate syntect;
 
+mod bundles;
+mod config;
 mod errors;
+mod export;
 mod gen_html;
+mod graph_functions;
+mod images;
+mod linkcheck;
+mod links;
+mod markdown;
+mod placeholders;
 mod post_graph;
 mod posts;
+mod query;
+mod routes;
+mod shortcodes;
+mod sitemap;
 
 use dialoguer::Confirmation;
 use errors::IOError;
-use gen_html::{create_posts, create_symlinks, gen_posts_html, get_templates};
+use gen_html::{
+    check_templates, create_posts, create_symlinks, gen_posts_html, gen_sitemap, gen_stubs,
+    get_templates, render_contents,
+};
+use images::ImageProcessor;
 use post_graph::Graph;
 use posts::PostTypes;
 use simplelog::{Config, LevelFilter, TermLogger};
-use std::fmt::{Debug, Display};
-use std::fs::{copy, create_dir, create_dir_all, read_dir, remove_dir_all};
+use std::error::Error;
+use std::fs::{copy, create_dir, create_dir_all, read_dir, remove_dir_all, write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use clap::{App, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
 fn argparse<'a>() -> ArgMatches<'a> {
     App::new("redsystem")
@@ -68,6 +94,12 @@ fn argparse<'a>() -> ArgMatches<'a> {
             .help("Directory to fetch content files from")
             .takes_value(true)
             .default_value("./posts"),
+        ).arg(Arg::with_name("config")
+            .long("config")
+            .short("c")
+            .help("Site configuration file; defaults are used if it doesn't exist")
+            .takes_value(true)
+            .default_value("./redsystem.toml"),
         ).arg(Arg::with_name("basepath")
             .long("base-path")
             .short("b")
@@ -84,28 +116,91 @@ fn argparse<'a>() -> ArgMatches<'a> {
             .long("print-graph")
             .short("g")
             .help("print a graphviz graph at the end of processing, to visually check the post relationship structure")
+        ).arg(Arg::with_name("graph_format")
+            .long("graph-format")
+            .help("Format of the printed graph")
+            .takes_value(true)
+            .possible_values(export::FORMATS)
+            .default_value("dot"),
+        ).arg(Arg::with_name("graph_out")
+            .long("graph-out")
+            .help("Write the graph to this file instead of printing it; implies -g")
+            .takes_value(true),
+        ).arg(Arg::with_name("graph_around")
+            .long("graph-around")
+            .help("Only print the part of the graph around this post (by name or alias)")
+            .takes_value(true),
+        ).arg(Arg::with_name("graph_radius")
+            .long("graph-radius")
+            .help("How many steps around --graph-around to include")
+            .takes_value(true)
+            .default_value("1"),
         ).arg(Arg::with_name("no_html")
             .long("no-html")
             .short("n")
             .help("Don't actually generate the posts. Useful for just validating structure, or with -g to only print the graph.")
+        ).subcommand(SubCommand::with_name("query")
+            .about("Answer questions about the post graph, without generating anything")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .arg(Arg::with_name("json")
+                .long("json")
+                .global(true)
+                .help("Print the answer as json")
+            ).subcommand(SubCommand::with_name("routes")
+                .about("Every route from the index down to a post")
+                .arg(Arg::with_name("name").required(true))
+            ).subcommand(SubCommand::with_name("path")
+                .about("The shortest route down from one post to another")
+                .arg(Arg::with_name("from").required(true))
+                .arg(Arg::with_name("to").required(true))
+            ).subcommand(SubCommand::with_name("ancestors")
+                .about("A post's parents, their parents, and so on, nearest first")
+                .arg(Arg::with_name("name").required(true))
+            ).subcommand(SubCommand::with_name("descendants")
+                .about("A post's children, their children, and so on, nearest first")
+                .arg(Arg::with_name("name").required(true))
+                .arg(Arg::with_name("depth")
+                    .long("depth")
+                    .takes_value(true)
+                    .help("How many levels down to go")
+                )
+            ).subcommand(SubCommand::with_name("edge")
+                .about("Which file declares that one post is the parent of another")
+                .arg(Arg::with_name("parent").required(true))
+                .arg(Arg::with_name("child").required(true))
+            ).subcommand(SubCommand::with_name("resolve")
+                .about("Which post a name or alias refers to; every name, if none is given")
+                .arg(Arg::with_name("name"))
+            ),
         ).get_matches()
 }
-fn unwraps_or_exits<T, E: Display + Debug>(t: Result<Vec<T>, Vec<E>>) -> Vec<T> {
+// an error, followed by whatever caused it (ie tera's "Failed to render" and the reason why)
+#[allow(deprecated)] // tera's errors only implement `cause`
+fn describe<E: Error>(e: &E) -> String {
+    let mut out = e.to_string();
+    let mut cause = e.cause();
+    while let Some(c) = cause {
+        out.push_str(&format!(": {}", c));
+        cause = c.cause();
+    }
+    out
+}
+fn unwraps_or_exits<T, E: Error>(t: Result<Vec<T>, Vec<E>>) -> Vec<T> {
     t.unwrap_or_else(|errors| {
-        errors.iter().for_each(|e| error!("{:?}", e));
+        errors.iter().for_each(|e| error!("{}", describe(e)));
         std::process::exit(1)
     })
 }
-fn unwrap_or_exits<T, E: Display + Debug>(t: Result<T, Vec<E>>) -> T {
+fn unwrap_or_exits<T, E: Error>(t: Result<T, Vec<E>>) -> T {
     t.unwrap_or_else(|errors| {
-        errors.iter().for_each(|e| error!("{:?}", e));
+        errors.iter().for_each(|e| error!("{}", describe(e)));
         std::process::exit(1)
     })
 }
 
-fn unwrap_or_exit<T, E: Display + Debug>(t: Result<T, E>) -> T {
+fn unwrap_or_exit<T, E: Error>(t: Result<T, E>) -> T {
     t.unwrap_or_else(|e| {
-        error!("{:?}", e);
+        error!("{}", describe(&e));
         std::process::exit(1)
     })
 }
@@ -152,9 +247,12 @@ fn create_www(wwwdir: &PathBuf, cssdir: &PathBuf, imgdir: &PathBuf, force_del: b
     trace!("Moving {:?} to {:?}", cssdir, &wwwdir.join("css"));
     checkdir(cssdir);
     unwrap_or_exit(copy_dir(&cssdir, &wwwdir.join("css")));
-    trace!("Moving {:?} to {:?}", imgdir, &wwwdir.join("img"));
-    checkdir(imgdir);
-    unwrap_or_exit(copy_dir(&imgdir, &wwwdir.join("img")));
+    // as is, for bodies, css and templates; post images also get hashed copies, alongside.
+    // git doesn't keep empty directories, so it's fine for it not to be there
+    if imgdir.is_dir() {
+        trace!("Moving {:?} to {:?}", imgdir, &wwwdir.join("img"));
+        unwrap_or_exit(copy_dir(&imgdir, &wwwdir.join("img")));
+    }
 }
 fn run_webserver(wwwdir: &PathBuf) {
     info!("Running webserver on 127.0.0.1:3000 ...");
@@ -179,25 +277,36 @@ fn main() {
     };
     let strictmode = args.is_present("strict");
 
+    // queries print their answer on stdout, so keep it clear of progress messages
+    let quiet = args.subcommand_matches("query").is_some();
     let loglevel = match args.occurrences_of("v") {
+        0 if quiet => LevelFilter::Warn,
         0 => LevelFilter::Info,
         1 => LevelFilter::Debug,
         _ => LevelFilter::Trace,
     };
     TermLogger::init(loglevel, Config::default()).unwrap();
+    let config = unwrap_or_exit(config::get_config(Path::new(getval("config"))));
 
     // read out the markdown to structs
     info!("Parsing posts..");
     let mut posts: Vec<_> = unwraps_or_exits(posts::get_posts(&postdir.to_path_buf()));
     if !strictmode {
-        let fakes = posts::get_fakeposts(&posts);
-        for f in &fakes {
-            info!(
-                "Could not find parent {}... generating empty post for it",
-                f.name()
-            )
+        let fakes = placeholders::get_placeholders(&posts, &config.placeholders);
+        for (_, missing) in &fakes {
+            let message = format!(
+                "Could not find parent {}{} (named by {})... generating a placeholder for it",
+                missing.name,
+                errors::did_you_mean(&missing.suggestions),
+                missing.referenced_by.join(", ")
+            );
+            if missing.suggestions.is_empty() {
+                info!("{}", message)
+            } else {
+                warn!("{}", message)
+            }
         }
-        posts.extend(fakes);
+        posts.extend(fakes.into_iter().map(|(f, _)| f));
     }
     // graph based on parents; we'll generate the symlinks from the graph.
     debug!("Constructing graph");
@@ -206,25 +315,81 @@ fn main() {
 
     unwrap_or_exits(graph.add_edges(&posts));
 
+    // [[wiki links]] in bodies; only fatal in strict mode
+    let links = graph.check_links(&posts);
+    if strictmode {
+        unwrap_or_exits(links);
+    } else if let Err(errors) = links {
+        errors.iter().for_each(|e| warn!("{}", e));
+    }
+    graph.add_links(&posts);
+
+    if let Some(q) = args.subcommand_matches("query") {
+        println!("{}", unwrap_or_exit(query::run(&graph, q, &config.routes)));
+        return;
+    }
+
+    // templates are checked now, so a post naming a missing one fails before anything is built
+    debug!("Fetching templates from {:?}", templateglob);
+    let mut tera = get_templates(&templateglob, config.markdown);
+    unwrap_or_exit(shortcodes::add_shortcodes(&mut tera, &templatedir.join("shortcodes")));
+    unwrap_or_exits(check_templates(&tera, &posts, &config));
+
     for post in &posts {
         post.set_children_names(graph.get_children_names(&post));
         post.set_parent_names(graph.get_parent_names(&post));
+        post.set_backlinks(graph.get_backlink_names(&post));
+        post.set_related(graph.get_related_names(&post, &config.related));
     }
 
     if !args.is_present("no_html") {
-        debug!("Fetching templates from {:?}", templateglob);
-        let tera = get_templates(&templateglob);
-
         create_www(&wwwdir, &cssdir, &imgdir, args.is_present("delete_outdir"));
+        unwrap_or_exit(markdown::write_highlight_css(&wwwdir.join("css"), &config.highlight.theme));
+        debug!("Copying page bundles");
+        unwrap_or_exit(bundles::copy_bundles(&posts, &wwwdir));
+        debug!("Processing images");
+        let mut processor = ImageProcessor::new(&wwwdir, &basepath, &config.images);
+        let images = images::process_images(&posts, &imgdir, &mut processor);
+        if strictmode {
+            unwrap_or_exits(images);
+        } else if let Err(errors) = images {
+            errors.iter().for_each(|e| warn!("{}", e));
+        }
         // struct -> html
         info!("Generating html..");
-        let post_templates = unwraps_or_exits(gen_posts_html(&tera, &posts, &graph, &basepath));
+        // the graph functions go in first so shortcodes can use them, and are refreshed once the
+        // bodies are rendered, so pages see the summaries derived from them
+        let map = Arc::new(sitemap::Map::from_graph(&graph, &basepath));
+        let functions = graph_functions::add_graph_functions(&mut tera, &graph, map.clone(), &basepath);
+        unwrap_or_exits(render_contents(&tera, &posts, &graph, &basepath, &config));
+        functions.refresh(&graph, &basepath);
+        let mut post_templates = unwraps_or_exits(gen_posts_html(&tera, &posts, &graph, &basepath, &config));
+        // the site map; the page is optional, for template dirs from before it existed
+        let svg = map.to_svg(None);
+        unwrap_or_exit(sitemap::write_svg(&wwwdir, &svg));
+        if tera.templates.contains_key("sitemap.jinja2") {
+            post_templates.push(unwrap_or_exit(gen_sitemap(&tera, &svg, &basepath)));
+        }
+        // likewise the list of placeholders still waiting for a real post
+        if tera.templates.contains_key("stubs.jinja2") {
+            post_templates.push(unwrap_or_exit(gen_stubs(&tera, &posts, &basepath)));
+            let stubs = posts.iter().filter(|p| p.is_placeholder()).count();
+            if stubs > 0 {
+                info!("{} placeholder(s) still need a post; see stubs.html", stubs);
+            }
+        }
         // generate the actual files and symlinks
         debug!("Writing posts");
         unwrap_or_exit(create_posts(&wwwdir, &post_templates));
+        debug!("Checking links");
+        let broken = linkcheck::check_output(&wwwdir, &post_templates, &basepath);
+        if strictmode {
+            unwrap_or_exits(broken);
+        } else if let Err(errors) = broken {
+            errors.iter().for_each(|e| warn!("{}", e));
+        }
         // debug!("Writing symlinks");
         // unwrap_or_exit(create_symlinks(&wwwdir, &srcdir, &post_templates));
-        // unwrap_or_exit(create_symlinks(&wwwdir, &srcdir, &graph));
         info!("Finished");
     }
     if args.is_present("run_server") {
@@ -232,8 +397,28 @@ fn main() {
         run_webserver(&wwwdir);
     }
 
-    if args.is_present("print_graph") {
-        println!("Use the following digraph on http://www.webgraphviz.com");
-        println!("{}", graph.dot());
+    if args.is_present("print_graph") || args.is_present("graph_out") {
+        let format = getval("graph_format");
+        let keep = args.value_of("graph_around").map(|name| {
+            let ix = graph.resolve(name).unwrap_or_else(|| {
+                error!("--graph-around: no post named {:?}", name);
+                std::process::exit(1)
+            });
+            let radius = getval("graph_radius").parse().unwrap_or_else(|e| {
+                error!("--graph-radius: {}", e);
+                std::process::exit(1)
+            });
+            graph.neighbourhood(ix, radius)
+        });
+        let exported = export::export(&graph, format, keep.as_ref());
+        match args.value_of("graph_out") {
+            Some(file) => unwrap_or_exit(write(file, exported)),
+            None => {
+                if format == "dot" {
+                    println!("Use the following digraph on http://www.webgraphviz.com");
+                }
+                println!("{}", exported);
+            }
+        }
     }
 }
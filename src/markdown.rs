@@ -0,0 +1,242 @@
+use config::MarkdownConfig;
+use errors;
+use errors::IOError::*;
+use pulldown_cmark::{html, CodeBlockKind, Event, Options, Parser, Tag};
+use quick_error::ResultExt;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+// prefixed so the highlighter's classes can't collide with the site's own
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+lazy_static! {
+    static ref SYNTAXES: SyntaxSet = SyntaxSet::load_defaults_newlines();
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct TocEntry {
+    pub level: usize,
+    pub id: String,
+    pub title: String,
+}
+
+pub struct Rendered {
+    pub html: String,
+    pub toc: Vec<TocEntry>,
+}
+
+pub fn render_markdown(s: &str, config: &MarkdownConfig) -> Rendered {
+    let mut options = Options::empty();
+    if config.tables {
+        options.insert(Options::ENABLE_TABLES);
+    }
+    if config.footnotes {
+        options.insert(Options::ENABLE_FOOTNOTES);
+    }
+    if config.strikethrough {
+        options.insert(Options::ENABLE_STRIKETHROUGH);
+    }
+    if config.tasklists {
+        options.insert(Options::ENABLE_TASKLISTS);
+    }
+    if config.smart_punctuation {
+        options.insert(Options::ENABLE_SMART_PUNCTUATION);
+    }
+    let events = highlight_code(Parser::new_ext(s, options));
+    let (events, toc) = anchor_headings(events, config.heading_anchors);
+
+    let mut html_buf = String::new();
+    html::push_html(&mut html_buf, events.into_iter());
+    Rendered {
+        html: html_buf,
+        toc,
+    }
+}
+
+pub const MORE_MARKER: &str = "<!-- more -->";
+
+// Plain text summary of a markdown body: everything before the `<!-- more -->` marker if there
+// is one, otherwise the first `words` words.
+pub fn summarize(body: &str, config: &MarkdownConfig, words: usize) -> String {
+    let (text, cut) = match body.find(MORE_MARKER) {
+        Some(ix) => (&body[..ix], false),
+        None => (body, true),
+    };
+    let text = strip_tags(&render_markdown(text, config).html);
+    let all: Vec<_> = text.split_whitespace().collect();
+    if cut && all.len() > words {
+        format!("{}…", all[..words].join(" "))
+    } else {
+        all.join(" ")
+    }
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                out.push(' ');
+            }
+            c if !in_tag => out.push(c),
+            _ => (),
+        }
+    }
+    out
+}
+
+// Collects every heading into the table of contents, giving each a stable id derived from its
+// text (deduplicated with -1, -2, ... in document order). Ids are only written out as anchors
+// when enabled; the toc is always built.
+fn anchor_headings<'a>(events: Vec<Event<'a>>, anchors: bool) -> (Vec<Event<'a>>, Vec<TocEntry>) {
+    let mut out = vec![];
+    let mut toc = vec![];
+    let mut used = HashSet::new();
+    let mut heading: Option<(usize, String, Vec<Event<'a>>)> = None; // (level, text, inner events)
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading(level, _, _)) => {
+                heading = Some((level as usize, String::new(), vec![]));
+            }
+            Event::End(Tag::Heading(level, _, classes)) => {
+                let (lvl, text, inner) = heading.take().unwrap_or_default();
+                let id = unique_slug(&text, &mut used);
+                if anchors {
+                    out.push(Event::Html(format!("<h{} id=\"{}\">", lvl, id).into()));
+                    out.extend(inner);
+                    out.push(Event::Html(format!("</h{}>\n", lvl).into()));
+                } else {
+                    out.push(Event::Start(Tag::Heading(level, None, classes.clone())));
+                    out.extend(inner);
+                    out.push(Event::End(Tag::Heading(level, None, classes)));
+                }
+                toc.push(TocEntry {
+                    level: lvl,
+                    id,
+                    title: text,
+                });
+            }
+            e => match heading {
+                Some((_, ref mut text, ref mut inner)) => {
+                    match e {
+                        Event::Text(ref t) | Event::Code(ref t) => text.push_str(t),
+                        _ => (),
+                    }
+                    inner.push(e);
+                }
+                None => out.push(e),
+            },
+        }
+    }
+    (out, toc)
+}
+
+fn unique_slug(text: &str, used: &mut HashSet<String>) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = match slug.trim_matches('-') {
+        "" => "section".to_string(),
+        s => s.to_string(),
+    };
+    let mut candidate = slug.clone();
+    let mut n = 0;
+    while used.contains(&candidate) {
+        n += 1;
+        candidate = format!("{}-{}", slug, n);
+    }
+    used.insert(candidate.clone());
+    candidate
+}
+
+// Fenced code blocks with a language tag that syntect recognises are replaced by pre-highlighted
+// html; anything else (no tag, unknown language) is passed through untouched.
+fn highlight_code<'a, I: Iterator<Item = Event<'a>>>(events: I) -> Vec<Event<'a>> {
+    let mut out = vec![];
+    let mut block: Option<(String, String)> = None; // (language, code)
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref lang)))
+                if find_syntax(lang).is_some() =>
+            {
+                block = Some((lang.to_string(), String::new()));
+            }
+            Event::Text(ref text) if block.is_some() => {
+                if let Some((_, ref mut code)) = block {
+                    code.push_str(text);
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) if block.is_some() => {
+                let (lang, code) = block.take().unwrap();
+                out.push(Event::Html(highlight(&lang, &code).into()));
+            }
+            e => out.push(e),
+        }
+    }
+    out
+}
+
+fn find_syntax(lang: &str) -> Option<&'static ::syntect::parsing::SyntaxReference> {
+    // the info string may carry more than the language, ie "rust,ignore"
+    let token = lang.split(|c: char| c == ',' || c.is_whitespace()).next().unwrap_or("");
+    if token.is_empty() {
+        return None;
+    }
+    SYNTAXES
+        .find_syntax_by_token(token)
+        .or_else(|| SYNTAXES.find_syntax_by_name(token))
+}
+
+fn highlight(lang: &str, code: &str) -> String {
+    let syntax = find_syntax(lang).unwrap();
+    let mut gen = ClassedHTMLGenerator::new_with_class_style(syntax, &SYNTAXES, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        if gen.parse_html_for_line_which_includes_newline(line).is_err() {
+            // syntect gave up on this block; fall back to plain escaped text
+            return format!("<pre><code>{}</code></pre>\n", escape(code));
+        }
+    }
+    format!(
+        "<pre class=\"hl-code\"><code class=\"language-{}\">{}</code></pre>\n",
+        escape(lang),
+        gen.finalize()
+    )
+}
+
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn load_theme(theme: &str) -> Result<Theme, errors::IOError> {
+    let mut themes = ThemeSet::load_defaults().themes;
+    themes.remove(theme).ok_or_else(|| {
+        let available: Vec<_> = ThemeSet::load_defaults().themes.keys().cloned().collect();
+        invalid_theme(theme.to_string(), format!("expected one of {:?}", available))
+    })
+}
+
+// the stylesheet for highlighted code, written next to style.css
+pub fn write_highlight_css(cssdir: &Path, theme: &str) -> Result<(), errors::IOError> {
+    let theme = load_theme(theme)?;
+    let css = css_for_theme_with_class_style(&theme, CLASS_STYLE)
+        .map_err(|e| invalid_theme(format!("{:?}", theme.name), e.to_string()))?;
+    let target = cssdir.join("highlight.css");
+    fs::write(&target, css).context(target.as_path())?;
+    Ok(())
+}
@@ -0,0 +1,242 @@
+use config::{PlaceholderConfig, PlaceholderKind};
+use post_graph::{full_name, lookup, suggest};
+use posts::{Category, Post, PostTypes};
+use std::collections::HashMap;
+
+// A parent that no post is named after, and everything we know about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Missing {
+    pub name: String,               // full name, which the placeholder is given
+    pub referenced_by: Vec<String>, // posts that list it as a parent, in the order they were read
+    pub suggestions: Vec<String>,   // existing names it may be a typo of
+}
+
+// Every missing parent, once, however many posts name it or however they spell it. Names are
+// looked up the way the graph will, so a parent that resolves (to a post, or to another
+// placeholder) doesn't get one. With
+// `refuse_near_misses`, parents that look like a typo aren't included.
+pub fn find_missing(posts: &[PostTypes], config: &PlaceholderConfig) -> Vec<Missing> {
+    let existing: HashMap<_, _> = posts
+        .iter()
+        .flat_map(|item| item.names().into_iter().map(|n| (n, None)))
+        .collect();
+    let references: Vec<_> = posts
+        .iter()
+        .flat_map(|item| item.parents().iter().map(move |p| (item, p)))
+        .filter(|&(_, p)| p != "INDEX")
+        .collect();
+
+    // first every name that's missing, even with the placeholders so far...
+    let mut names = existing.clone();
+    let mut missing: Vec<Missing> = vec![];
+    for &(item, parent) in &references {
+        if lookup(&names, parent, item.dirname()).is_some() {
+            continue;
+        }
+        let suggestions = suggest(&existing, parent, item.dirname());
+        if config.refuse_near_misses && !suggestions.is_empty() {
+            continue;
+        }
+        if let Some(name) = full_name(parent, item.dirname()) {
+            names.insert(name.clone(), Some(missing.len()));
+            missing.push(Missing {
+                name,
+                referenced_by: vec![],
+                suggestions,
+            });
+        }
+    }
+    // ...then which posts end up pointing at which, now that they all exist. A later placeholder
+    // can be a better match for an earlier reference (ie `Category/Foo` for `Foo`, from
+    // `Category`), leaving the earlier one unused.
+    for &(item, parent) in &references {
+        if let Some(Some(i)) = lookup(&names, parent, item.dirname()) {
+            if !missing[i].referenced_by.contains(&item.name()) {
+                missing[i].referenced_by.push(item.name());
+            }
+        }
+    }
+    missing.retain(|m| !m.referenced_by.is_empty());
+    missing
+}
+
+// An empty page standing in for a missing parent, titled with its name. A name with a dirname
+// (`Category/Foo`) is put in that dirname.
+pub fn placeholder(missing: &Missing, config: &PlaceholderConfig) -> PostTypes {
+    let (dirname, name) = match missing.name.rfind('/') {
+        Some(i) => (missing.name[..i].to_string(), missing.name[i + 1..].to_string()),
+        None => (String::new(), missing.name.clone()),
+    };
+    match config.kind {
+        PlaceholderKind::Category => PostTypes::Category(Category {
+            title: name.clone(),
+            name,
+            dirname,
+            template: config.template.clone(),
+            placeholder: true,
+            ..Default::default()
+        }),
+        PlaceholderKind::Post => PostTypes::Post(Post {
+            title: name.clone(),
+            name,
+            dirname,
+            template: config.template.clone(),
+            placeholder: true,
+            ..Default::default()
+        }),
+    }
+}
+
+pub fn get_placeholders(posts: &[PostTypes], config: &PlaceholderConfig) -> Vec<(PostTypes, Missing)> {
+    find_missing(posts, config)
+        .into_iter()
+        .map(|m| (placeholder(&m, config), m))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use post_graph::Graph;
+
+    fn post(name: &str, dirname: &str, parents: &[&str]) -> PostTypes {
+        PostTypes::Post(Post {
+            name: name.to_string(),
+            dirname: dirname.to_string(),
+            parents: parents.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    fn names(missing: &[Missing]) -> Vec<&str> {
+        missing.iter().map(|m| m.name.as_str()).collect()
+    }
+
+    #[test]
+    fn one_placeholder_per_missing_name() {
+        let posts = vec![
+            post("a", "", &["Gone"]),
+            post("b", "", &["Gone", "Gone"]),
+            post("c", "", &["Gone", "a"]),
+        ];
+        let missing = find_missing(&posts, &PlaceholderConfig::default());
+        assert_eq!(names(&missing), vec!["Gone"]);
+        assert_eq!(missing[0].referenced_by, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn existing_names_aliases_and_the_index_are_not_missing() {
+        let mut aliased = post("Star Control", "Category", &[]);
+        if let PostTypes::Post(ref mut p) = aliased {
+            p.aliases = vec!["sc".to_string()];
+        }
+        let posts = vec![
+            aliased,
+            post("a", "", &["INDEX", "Category/sc", "Category/Star Control"]),
+            post("b", "Category", &["sc", "/Category/Star Control", "./sc"]),
+        ];
+        assert!(find_missing(&posts, &PlaceholderConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn placeholders_keep_the_dirname_of_the_missing_name() {
+        let posts = vec![post("a", "", &["Category/Sub/Gone"]), post("b", "Category/Sub", &["../Other"])];
+        let config = PlaceholderConfig::default();
+        let missing = find_missing(&posts, &config);
+        assert_eq!(names(&missing), vec!["Category/Sub/Gone", "Category/Other"]);
+
+        let gone = placeholder(&missing[0], &config);
+        assert_eq!(gone.dirname(), "Category/Sub");
+        assert_eq!(gone.basename(), "Gone");
+        assert_eq!(gone.name(), "Category/Sub/Gone");
+    }
+
+    #[test]
+    fn references_share_the_placeholder_they_resolve_to() {
+        // `Gone` from Category finds `Category/Gone` first, once that exists
+        let posts = vec![post("a", "Category", &["Gone"]), post("b", "", &["Category/Gone"])];
+        let missing = find_missing(&posts, &PlaceholderConfig::default());
+        assert_eq!(names(&missing), vec!["Category/Gone"]);
+        assert_eq!(missing[0].referenced_by, vec!["Category/a", "b"]);
+    }
+
+    #[test]
+    fn out_of_range_names_are_left_to_the_graph() {
+        let posts = vec![post("a", "", &["../Nowhere"])];
+        assert!(find_missing(&posts, &PlaceholderConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn names_in_other_dirnames_are_not_found() {
+        // `Mecha` from the top means `Mecha`, not `Category/Mecha`
+        let posts = vec![post("Mecha", "Category", &[]), post("a", "", &["Mecha"])];
+        let missing = find_missing(&posts, &PlaceholderConfig::default());
+        assert_eq!(names(&missing), vec!["Mecha"]);
+        assert_eq!(missing[0].suggestions, vec!["Category/Mecha"]);
+    }
+
+    #[test]
+    fn near_misses_are_suggested_or_refused() {
+        let posts = vec![post("Star Control", "", &[]), post("a", "", &["Star Contrl", "Elsewhere"])];
+        let missing = find_missing(&posts, &PlaceholderConfig::default());
+        assert_eq!(names(&missing), vec!["Star Contrl", "Elsewhere"]);
+        assert_eq!(missing[0].suggestions, vec!["Star Control"]);
+        assert!(missing[1].suggestions.is_empty());
+
+        let refuse = PlaceholderConfig {
+            refuse_near_misses: true,
+            ..Default::default()
+        };
+        assert_eq!(names(&find_missing(&posts, &refuse)), vec!["Elsewhere"]);
+    }
+
+    #[test]
+    fn placeholders_are_marked_categories_unless_configured() {
+        let missing = Missing {
+            name: "Gone".to_string(),
+            referenced_by: vec!["a".to_string()],
+            suggestions: vec![],
+        };
+        let category = placeholder(&missing, &PlaceholderConfig::default());
+        assert!(category.is_placeholder());
+        assert!(match category {
+            PostTypes::Category(_) => true,
+            _ => false,
+        });
+        assert_eq!(category.title(), "Gone");
+
+        let config = PlaceholderConfig {
+            kind: PlaceholderKind::Post,
+            template: Some("stub.jinja2".to_string()),
+            ..Default::default()
+        };
+        let post = placeholder(&missing, &config);
+        assert!(post.is_placeholder());
+        assert_eq!(post.template(), Some("stub.jinja2"));
+        assert!(match post {
+            PostTypes::Post(_) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn placeholders_complete_the_graph() {
+        let mut posts = vec![
+            post("a", "", &["Gone", "Category/Gone"]),
+            post("b", "Category", &["Gone"]),
+            post("c", "", &["a"]),
+        ];
+        let placeholders = get_placeholders(&posts, &PlaceholderConfig::default());
+        posts.extend(placeholders.into_iter().map(|(p, _)| p));
+        assert_eq!(posts.len(), 5);
+
+        let mut graph = Graph::new();
+        graph.add_posts(&posts);
+        assert!(graph.add_edges(&posts).is_ok());
+        let gone = graph.resolve("Category/Gone").unwrap();
+        assert_eq!(graph.children(gone).len(), 2); // a and Category/b
+        let also_gone = graph.resolve("Gone").unwrap();
+        assert_ne!(gone, also_gone);
+        assert_eq!(graph.children(also_gone).len(), 1); // a
+    }
+}
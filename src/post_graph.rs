@@ -1,20 +1,37 @@
+use config::{RelatedConfig, RouteConfig};
 use errors::GraphError;
+use links::{find_wikilinks, node_url};
 use petgraph;
 use petgraph::graph::NodeIndex;
 use petgraph::visit::{EdgeRef, NodeIndexable};
 use posts::{Category, Post, PostTypes};
+use routes::Routes;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::cmp;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
 use std::collections::HashSet;
+use std::hash::Hash;
+use strsim::levenshtein;
 #[derive(Debug)]
 pub enum PostNode<'a> {
     Node(&'a PostTypes),
     Root(),
 }
+// Parent edges come from the `parent` header and define the site structure;
+// Link edges come from [[links]] in the body, and only feed backlinks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeType {
+    Parent,
+    Link,
+}
 #[derive(Default)]
 pub struct Graph<'a> {
     pub root: petgraph::graph::NodeIndex,
-    pub graph: petgraph::Graph<PostNode<'a>, usize>,
+    pub graph: petgraph::Graph<PostNode<'a>, EdgeType>,
     name_map: HashMap<String, petgraph::graph::NodeIndex>,
+    child_order: HashMap<NodeIndex, Vec<NodeIndex>>, // from categories' `children` lists
 }
 
 impl<'a> Graph<'a> {
@@ -26,6 +43,7 @@ impl<'a> Graph<'a> {
             root,
             graph,
             name_map,
+            child_order: HashMap::new(),
         }
     }
     pub fn add_posts(self: &mut Self, items: &'a Vec<PostTypes>) {
@@ -35,29 +53,25 @@ impl<'a> Graph<'a> {
         }
     }
 
-    pub fn get_fakeposts(self: &mut Self, items: &'a [PostTypes]) -> Vec<PostTypes> {
-        // then generate fake posts for all missing parents
-        items
-            .iter()
-            .flat_map(|item| item.parents())
-            .filter(|&p| !self.name_map.contains_key(p))
-            .map(|parent| {
-                PostTypes::Post(Post {
-                    name: parent.clone(),
-                    ..Default::default()
-                })
-            }).collect()
-    }
-
     pub fn add_edges(self: &mut Self, items: &'a [PostTypes]) -> Result<(), Vec<GraphError>> {
         // Add the posts to the graph
         let mut errors: Vec<GraphError> = Vec::new();
 
+        // children listed by categories go first, so that a post without parents of its own
+        // only ends up under the index when no category lists it
+        for item in items.iter().filter(|item| !item.listed_children().is_empty()) {
+            if let Err(x) = self.add_listed_children(item) {
+                errors.extend(x);
+            }
+        }
+
         // set the relationships based on stated parent relationship
         for item in items {
-            match self.add_edge(&item.name(), &item.parents()) {
-                Ok(()) => (),
-                Err(x) => errors.push(x),
+            if item.parents().is_empty() && !self.parents(self.name_map[&item.name()]).is_empty() {
+                continue;
+            }
+            if let Err(x) = self.add_edge(&item.name(), &item.parents()) {
+                errors.extend(x);
             }
         }
 
@@ -68,39 +82,318 @@ impl<'a> Graph<'a> {
         }
     }
 
+    // Edges from a category's `children` list, in its order; a child that lists the category in
+    // its `not_parent` is a conflict, and isn't added. Children are looked up from the category's
+    // dirname.
+    fn add_listed_children(self: &mut Self, item: &'a PostTypes) -> Result<(), Vec<GraphError>> {
+        let parent = self.name_map[&item.name()];
+        let dir = item.dirname();
+        let mut order = vec![];
+        let mut missing_children = vec![];
+        let mut errors = vec![];
+        for name in item.listed_children() {
+            match self.resolve_from(name, dir) {
+                // the index can't be anyone's child
+                Some(child) if child == self.root => missing_children.push((name.to_string(), vec![])),
+                Some(child) => {
+                    let post = self.post(child).unwrap();
+                    let excluded = post
+                        .excluded_parents()
+                        .iter()
+                        .any(|p| self.resolve_from(p, post.dirname()) == Some(parent));
+                    if excluded {
+                        errors.push(GraphError::ExcludedChildError(item.name(), post.name()));
+                    } else if !order.contains(&child) {
+                        order.push(child);
+                    }
+                }
+                None => missing_children.push((name.to_string(), self.suggest(name, dir))),
+            }
+        }
+        if !missing_children.is_empty() {
+            errors.insert(0, GraphError::MissingChildError(item.name(), missing_children));
+        }
+        for &child in &order {
+            self.graph.add_edge(parent, child, EdgeType::Parent);
+        }
+        self.child_order.insert(parent, order);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // one Link edge per (linking post, linked post); links to self or the index are skipped.
+    // Unresolved links are reported by check_links, so they're ignored here.
+    pub fn add_links(self: &mut Self, items: &'a [PostTypes]) {
+        for item in items {
+            let source = self.name_map[&item.name()];
+            let mut targets: Vec<_> = find_wikilinks(item.body())
+                .iter()
+                .filter_map(|link| self.resolve_from(&link.target, item.dirname()))
+                .filter(|&target| target != source && target != self.root)
+                .collect();
+            targets.sort_unstable();
+            targets.dedup();
+            for target in targets {
+                self.graph.add_edge(source, target, EdgeType::Link);
+            }
+        }
+    }
+
+    // every name and alias, with the node it refers to
+    pub fn names(self: &Self) -> impl Iterator<Item = (&String, &NodeIndex)> {
+        self.name_map.iter()
+    }
+
     pub fn getidx(self: &Self, postname: &str) -> &NodeIndex {
         &self.name_map[postname]
     }
 
+    pub fn children(self: &Self, idx: NodeIndex) -> Vec<NodeIndex> {
+        self.graph
+            .edges(idx)
+            .filter(|e| *e.weight() == EdgeType::Parent)
+            .map(|e| e.target())
+            .collect()
+    }
+
+    pub fn parents(self: &Self, idx: NodeIndex) -> Vec<NodeIndex> {
+        self.graph
+            .edges_directed(idx, petgraph::Direction::Incoming)
+            .filter(|e| *e.weight() == EdgeType::Parent)
+            .map(|e| e.source())
+            .collect()
+    }
+
+    // children in the order their parent lists them (`children = [...]`), then the rest by name
+    pub fn ordered_children(self: &Self, idx: NodeIndex) -> Vec<NodeIndex> {
+        let listed = self.child_order.get(&idx).map_or(&[][..], |o| &o[..]);
+        let mut rest: Vec<_> = self.children(idx).into_iter().filter(|c| !listed.contains(c)).collect();
+        rest.sort_unstable_by_key(|&c| self.ix_to_name(c));
+        rest.dedup();
+        listed.iter().cloned().chain(rest).collect()
+    }
+
+    pub fn post(self: &Self, idx: NodeIndex) -> Option<&'a PostTypes> {
+        match self.graph[idx] {
+            PostNode::Node(p) => Some(p),
+            PostNode::Root() => None,
+        }
+    }
+
+    // full name, as it would be referenced; the index is "INDEX"
+    pub fn node_name(self: &Self, idx: NodeIndex) -> String {
+        match self.graph[idx] {
+            PostNode::Node(p) => p.name(),
+            PostNode::Root() => "INDEX".to_string(),
+        }
+    }
+
+    // breadth-first along parent (or, with `up`, child) relationships, nearest first, up to
+    // `depth` steps; the node itself isn't included
+    pub fn walk(self: &Self, idx: NodeIndex, up: bool, depth: Option<usize>) -> Vec<NodeIndex> {
+        breadth_first(idx, depth, |ix| {
+            let mut step = if up { self.parents(ix) } else { self.children(ix) };
+            step.sort_unstable_by_key(|&n| self.node_name(n));
+            step
+        })
+    }
+
+    // the shortest chain of children from one node down to another, both ends included
+    pub fn shortest_path(self: &Self, from: NodeIndex, to: NodeIndex) -> Option<Vec<NodeIndex>> {
+        shortest_chain(from, to, |ix| {
+            let mut children = self.children(ix);
+            children.sort_unstable_by_key(|&n| self.node_name(n));
+            children
+        })
+    }
+
+    // hops down from the index to every node it can reach
+    fn depths(self: &Self) -> HashMap<NodeIndex, usize> {
+        let mut depths = HashMap::new();
+        depths.insert(self.root, 0);
+        let mut frontier = vec![self.root];
+        let mut d = 0;
+        while !frontier.is_empty() {
+            d += 1;
+            let mut next = vec![];
+            for ix in frontier {
+                for child in self.children(ix) {
+                    if !depths.contains_key(&child) {
+                        depths.insert(child, d);
+                        next.push(child);
+                    }
+                }
+            }
+            frontier = next;
+        }
+        depths
+    }
+
+    // Routes down from the index to a node, without visiting anything twice, shortest first.
+    // Searched upwards from the node, best first: a partial route is extended in order of the
+    // shortest full route it could still become, so only as much of the graph is explored as
+    // the `max_routes` shortest routes need. Nothing longer than `max_depth` is considered.
+    pub fn routes_to(self: &Self, idx: NodeIndex, limits: &RouteConfig) -> Routes {
+        let depths = self.depths();
+        let mut routes = Routes::new();
+        let mut queue = BinaryHeap::new();
+        if let Some(&d) = depths.get(&idx) {
+            // (shortest possible length, order pushed, step); Reverse makes it a min-heap
+            queue.push(Reverse((d, 0, routes.push(idx, None))));
+        }
+        while let Some(Reverse((_, _, step))) = queue.pop() {
+            if routes.node(step) == self.root {
+                if routes.ends.len() == limits.max_routes {
+                    routes.truncated = true;
+                    break;
+                }
+                routes.ends.push(step);
+                continue;
+            }
+            let mut parents = self.parents(routes.node(step));
+            parents.sort_unstable_by_key(|&p| self.node_name(p));
+            for parent in parents {
+                // parents the index can't reach are dead ends
+                let estimate = match depths.get(&parent) {
+                    Some(&d) => routes.len(step) + 1 + d,
+                    None => continue,
+                };
+                if estimate > limits.max_depth {
+                    routes.truncated = true;
+                    continue;
+                }
+                let s = routes.push(parent, Some(step));
+                if routes.repeat(s).is_none() {
+                    queue.push(Reverse((estimate, routes.step_count(), s)));
+                }
+            }
+        }
+        routes
+    }
+
+    // look up a name/alias the way a post in `dir` would (see `lookup`); INDEX is the index
+    pub fn resolve_from(self: &Self, name: &str, dir: &str) -> Option<NodeIndex> {
+        match name {
+            "INDEX" => Some(self.root),
+            s => lookup(&self.name_map, s, dir),
+        }
+    }
+
+    // names a missing reference from `dir` was probably meant to be; see `suggest`
+    pub fn suggest(self: &Self, name: &str, dir: &str) -> Vec<String> {
+        suggest(&self.name_map, name, dir)
+    }
+
+    // look up a name/alias from the top, ie for a post without a dirname
+    pub fn resolve(self: &Self, name: &str) -> Option<NodeIndex> {
+        self.resolve_from(name, "")
+    }
+
+    pub fn url_for(self: &Self, ix: NodeIndex, basepath: &str) -> String {
+        match self.graph[ix] {
+            PostNode::Node(n) => node_url(&n.name(), basepath),
+            PostNode::Root() => format!("{}/", basepath),
+        }
+    }
+
+    // every [[link]] in a post body that doesn't resolve to a node
+    pub fn check_links(self: &Self, items: &[PostTypes]) -> Result<(), Vec<GraphError>> {
+        let errors: Vec<_> = items
+            .iter()
+            .flat_map(|item| {
+                find_wikilinks(item.body())
+                    .into_iter()
+                    .filter_map(move |link| match self.resolve_from(&link.target, item.dirname()) {
+                        Some(_) => None,
+                        None => {
+                            let suggestions = self.suggest(&link.target, item.dirname());
+                            Some(GraphError::UnresolvedLinkError(item.name(), link.target, suggestions))
+                        }
+                    })
+            }).collect();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     //TODO: Add sorting on names.
     pub fn get_child_cats(self: &Self, idx: NodeIndex) -> Vec<&Category> {
-        let mut out: Vec<_> = self
-            .graph
-            .neighbors(idx)
+        self.ordered_children(idx)
+            .into_iter()
             .map(|idx| &self.graph[idx])
             .map(|node| match node {
                 PostNode::Node(PostTypes::Category(c)) => Some(c),
                 _ => None,
             }).filter(Option::is_some)
             .map(Option::unwrap)
-            .collect();
-        out.sort_unstable_by(|&c1, &c2| c1.name.cmp(&c2.name));
-        out
+            .collect()
     }
 
     pub fn get_child_posts(self: &Self, idx: NodeIndex) -> Vec<&Post> {
-        let mut out: Vec<_> = self
-            .graph
-            .neighbors(idx)
+        self.ordered_children(idx)
+            .into_iter()
             .map(|idx| &self.graph[idx])
             .map(|node| match node {
                 PostNode::Node(PostTypes::Post(p)) => Some(p),
                 _ => None,
             }).filter(Option::is_some)
             .map(Option::unwrap)
-            .collect();
-        out.sort_unstable_by(|&p1, &p2| p1.name.cmp(&p2.name));
-        out
+            .collect()
+    }
+
+    // Every post below a node, through any mix of categories and posts, up to `depth` levels down.
+    // Each post is listed once, with the category it sits in on the shortest way down (None when
+    // it's a direct child), grouped by that category in the order they were reached, then by name.
+    pub fn get_descendant_posts(
+        self: &Self,
+        idx: NodeIndex,
+        depth: Option<usize>,
+    ) -> Vec<(Option<&Category>, &Post)> {
+        let mut seen: HashSet<_> = vec![idx].into_iter().collect();
+        let mut order = vec![None]; // groups, in the order they were reached
+        let mut found = vec![];
+        let mut frontier = vec![(idx, None)];
+        let mut d = 0;
+        while !frontier.is_empty() && depth.map_or(true, |max| d < max) {
+            d += 1;
+            let mut next = vec![];
+            for (ix, group) in frontier {
+                let mut children = self.children(ix);
+                children.sort_unstable_by_key(|&c| self.ix_to_name(c));
+                for child in children.into_iter().filter(|&c| seen.insert(c)) {
+                    let child_group = match self.graph[child] {
+                        // posts found below a post stay in that post's category
+                        PostNode::Node(PostTypes::Post(p)) => {
+                            found.push((group, p));
+                            group
+                        }
+                        PostNode::Node(PostTypes::Category(_)) => {
+                            order.push(Some(child));
+                            Some(child)
+                        }
+                        PostNode::Root() => continue,
+                    };
+                    next.push((child, child_group));
+                }
+            }
+            frontier = next;
+        }
+        found.sort_by_key(|&(group, p)| (order.iter().position(|&g| g == group), p.name.clone()));
+        found
+            .into_iter()
+            .map(|(group, p)| {
+                let cat = group.and_then(|g| match self.graph[g] {
+                    PostNode::Node(PostTypes::Category(c)) => Some(c),
+                    _ => None,
+                });
+                (cat, p)
+            }).collect()
     }
 
     pub fn get_children_names(self: &Self, post: &'a PostTypes) -> Vec<(String, String)> {
@@ -108,37 +401,137 @@ impl<'a> Graph<'a> {
         // which we'll use for the post's links.
 
         let idx = self.name_map[&post.name()];
-        let mut out: Vec<_> = self
-            .graph
-            .neighbors(idx)
+        self.ordered_children(idx)
+            .into_iter()
             .map(|ix| {
                 (
                     self.ix_to_name(ix).to_string(),
                     self.ix_to_title(ix).to_string(),
                 )
-            }).collect();
-        out.sort_unstable();
-        out
+            }).collect()
     }
 
     pub fn get_parent_names(self: &Self, post: &'a PostTypes) -> Vec<(String, String)> {
         // now do the inverse; read the defined relationships and determine the child-relationship
         // which we'll use for the post's links.
 
+        // the index's page is index.html, whatever the node's called
         let idx = self.name_map[&post.name()];
         let mut out: Vec<_> = self
-            .graph
-            .neighbors_directed(idx, petgraph::Direction::Incoming)
+            .parents(idx)
+            .into_iter()
             .map(|ix| {
+                let name = if ix == self.root { "index".to_string() } else { self.ix_to_name(ix) };
+                (name, self.ix_to_title(ix).to_string())
+            }).collect();
+        out.sort_unstable();
+        out
+    }
+
+    pub fn get_backlink_names(self: &Self, post: &'a PostTypes) -> Vec<(String, String)> {
+        let idx = self.name_map[&post.name()];
+        let mut out: Vec<_> = self
+            .graph
+            .edges_directed(idx, petgraph::Direction::Incoming)
+            .filter(|e| *e.weight() == EdgeType::Link)
+            .map(|e| {
                 (
-                    self.ix_to_name(ix).to_string(),
-                    self.ix_to_title(ix).to_string(),
+                    self.ix_to_name(e.source()).to_string(),
+                    self.ix_to_title(e.source()).to_string(),
                 )
             }).collect();
         out.sort_unstable();
         out
     }
 
+    // the posts (not categories) most related to this one, best first; see RelatedConfig. Only
+    // posts that can score are looked at: those nearby, those sharing a parent, and [[links]].
+    pub fn get_related_names(self: &Self, post: &'a PostTypes, config: &RelatedConfig) -> Vec<(String, String)> {
+        if config.count == 0 {
+            return vec![];
+        }
+        let idx = self.name_map[&post.name()];
+        let parents: HashSet<_> = self.parents(idx).into_iter().filter(|&p| p != self.root).collect();
+        let distances = self.undirected_distances(idx, config.max_distance);
+        let mut links: HashMap<NodeIndex, usize> = HashMap::new();
+        for e in self.graph.edges_directed(idx, petgraph::Direction::Outgoing) {
+            if *e.weight() == EdgeType::Link {
+                *links.entry(e.target()).or_insert(0) += 1;
+            }
+        }
+        for e in self.graph.edges_directed(idx, petgraph::Direction::Incoming) {
+            if *e.weight() == EdgeType::Link {
+                *links.entry(e.source()).or_insert(0) += 1;
+            }
+        }
+        let candidates: HashSet<_> = distances
+            .keys()
+            .cloned()
+            .chain(parents.iter().flat_map(|&p| self.children(p)))
+            .chain(links.keys().cloned())
+            .collect();
+        let mut scored: Vec<_> = candidates
+            .into_iter()
+            .filter(|&ix| ix != idx)
+            .filter(|&ix| match self.graph[ix] {
+                PostNode::Node(PostTypes::Post(_)) => true,
+                _ => false,
+            }).map(|ix| {
+                let shared = self
+                    .graph
+                    .edges_directed(ix, petgraph::Direction::Incoming)
+                    .filter(|e| *e.weight() == EdgeType::Parent && parents.contains(&e.source()))
+                    .count();
+                let linked = links.get(&ix).cloned().unwrap_or(0);
+                let near = distances
+                    .get(&ix)
+                    .map_or(0.0, |&d| config.distance / d as f64);
+                let score =
+                    shared as f64 * config.shared_parent + linked as f64 * config.link + near;
+                (score, ix)
+            }).filter(|&(score, _)| score > 0.0)
+            .collect();
+        // names only break ties, so only the best few (and whatever ties with the last) need one
+        scored.sort_by(|(s1, _), (s2, _)| s2.partial_cmp(s1).unwrap_or(Ordering::Equal));
+        if let Some(&(cutoff, _)) = scored.get(config.count - 1) {
+            scored.retain(|&(score, _)| score >= cutoff);
+        }
+        let mut named: Vec<_> = scored
+            .into_iter()
+            .map(|(score, ix)| (score, self.ix_to_name(ix), ix))
+            .collect();
+        named.sort_by(|(s1, n1, _), (s2, n2, _)| {
+            s2.partial_cmp(s1).unwrap_or(Ordering::Equal).then_with(|| n1.cmp(n2))
+        });
+        named
+            .into_iter()
+            .take(config.count)
+            .map(|(_, name, ix)| (name, self.ix_to_title(ix)))
+            .collect()
+    }
+
+    // hops to every node within `max` of this one, over edges of either type and direction;
+    // paths through the index don't count, or every top-level post would be 2 hops away
+    fn undirected_distances(self: &Self, idx: NodeIndex, max: usize) -> HashMap<NodeIndex, usize> {
+        let mut dist = HashMap::new();
+        dist.insert(idx, 0);
+        let mut frontier = vec![idx];
+        for d in 1..=max {
+            let mut next = vec![];
+            for ix in frontier {
+                for n in self.graph.neighbors_undirected(ix) {
+                    if n != self.root && !dist.contains_key(&n) {
+                        dist.insert(n, d);
+                        next.push(n);
+                    }
+                }
+            }
+            frontier = next;
+        }
+        dist.remove(&idx);
+        dist
+    }
+
     pub fn add_node(self: &mut Self, item: &'a PostTypes) -> petgraph::graph::NodeIndex {
         let node = self.graph.add_node(PostNode::Node(item));
 
@@ -148,27 +541,6 @@ impl<'a> Graph<'a> {
         node
     }
 
-    fn find_paths(
-        self: &Self,
-        nx: NodeIndex,
-        cur_route: &mut Vec<NodeIndex>,
-        all_routes: &mut Vec<Vec<NodeIndex>>,
-    ) {
-        if cur_route.contains(&nx) {
-            cur_route.push(nx); // keep the duplicate; we'll use it to produce the symlink cycle
-            info!("cur-route: {:?}", cur_route);
-            return;
-        }
-        cur_route.push(nx);
-        for child in self.graph.neighbors(nx) {
-            let mut new_route = cur_route.clone();
-            self.find_paths(child, &mut new_route, all_routes);
-            if cur_route.len() != new_route.len() {
-                all_routes.push(new_route);
-            }
-        }
-    }
-
     fn ix_to_name(self: &Self, ix: NodeIndex) -> String {
         match self.graph[ix] {
             PostNode::Node(n) => n.name(),
@@ -182,108 +554,250 @@ impl<'a> Graph<'a> {
         }
     }
 
-    fn ixs_to_name(self: &Self, ixs: &[NodeIndex]) -> Vec<String> {
-        ixs.iter().map(|&n| self.ix_to_name(n)).collect()
-    }
-
-    // the last item [MAY] be a duplicate as some other element in the path
-    // if it is, then it'll become a symlink-cycle.
-    // The optional element of the route is the index of the preceding element.
-    pub fn find_all_paths(self: &Self) -> Vec<(String, Vec<String>, Option<usize>)> {
-        let mut all_routes: Vec<_> = self.graph.neighbors(self.root).map(|nx| vec![nx]).collect();
-
-        for nx in self.graph.neighbors(self.root) {
-            let mut routes = vec![];
-            self.find_paths(nx, &mut vec![], &mut routes);
-            all_routes.append(&mut routes);
-        }
-
-        // (post, route_to_it, (index_of_duplicate))
-        all_routes
-            .iter()
-            .inspect(|r| info!("route: {:?}", r))
-            .map(|r| {
-                let end = r.len() - 1;
-                (
-                    self.ix_to_name(r[end]),
-                    self.ixs_to_name(&r[0..end]),
-                    r.iter()
-                        .enumerate()
-                        .position(|(i, &ix)| i != end && ix == r[end]),
-                )
-            }).collect()
-    }
-
-    pub fn add_edge(self: &mut Self, name: &str, parentlist: &[String]) -> Result<(), GraphError> {
+    pub fn add_edge(self: &mut Self, name: &str, parentlist: &[String]) -> Result<(), Vec<GraphError>> {
         let child = match self.name_map.get(name) {
             Some(&c) => c,
             None => panic!("Edge was added before the Node itself was: {}", name),
         };
         // add to root node
         if parentlist.is_empty() {
-            self.graph.add_edge(self.root, child, 0);
+            self.graph.add_edge(self.root, child, EdgeType::Parent);
             return Ok(());
         }
-        let map = &mut self.name_map;
-        let graph = &mut self.graph;
-        let root = &self.root;
-        let (parents, errors): (Vec<_>, Vec<_>) = parentlist
-            .iter()
-            .map(|parent| match parent.as_str() {
-                "INDEX" => Ok(root),
-                s => match map.get(s) {
-                    Some(post) => Ok(post),
-                    None => Err(parent),
-                },
-            }).partition(Result::is_ok);
-
+        // parents are looked up from the child's dirname
+        let dir = self.post(child).map_or("", |p| p.dirname());
+        let mut parents = vec![];
+        let mut missing_parents = vec![];
+        let mut errors = vec![];
+        for parent in parentlist {
+            match self.resolve_from(parent, dir) {
+                Some(ix) => parents.push(ix),
+                None => missing_parents.push((parent.to_string(), self.suggest(parent, dir))),
+            }
+        }
+        if !missing_parents.is_empty() {
+            errors.insert(0, GraphError::MissingEdgeError(name.to_string(), missing_parents));
+        }
         if !errors.is_empty() {
-            let missing_parents: Vec<String> =
-                errors.iter().map(|x| x.unwrap_err().to_string()).collect();
-            return Err(GraphError::MissingEdgeError(
-                name.to_string(),
-                missing_parents,
-            ));
+            return Err(errors);
         }
 
         // Silently ignore multiple references to the same parent, by a single post
-        let mut parents: Vec<_> = parents.iter().map(|x| x.unwrap()).collect();
         parents.sort_unstable();
         parents.dedup();
 
+        // or to a parent that already listed it in `children`
         for parent in parents {
-            graph.add_edge(*parent, child, 0);
+            if !self.children(parent).contains(&child) {
+                self.graph.add_edge(parent, child, EdgeType::Parent);
+            }
         }
         Ok(())
     }
 
-    // simplified graphviz code, based on petgraph's graph conversion source
-    // only prints out name() for the node labels
-    pub fn dot(self: &Self) -> String {
+    // Every node within `radius` steps of a node, following any edge either way; the node itself
+    // is included.
+    pub fn neighbourhood(self: &Self, idx: NodeIndex, radius: usize) -> HashSet<NodeIndex> {
+        let mut keep: HashSet<_> = vec![idx].into_iter().collect();
+        let mut frontier = vec![idx];
+        for _ in 0..radius {
+            let next: Vec<_> = frontier
+                .iter()
+                .flat_map(|&ix| self.graph.neighbors_undirected(ix))
+                .filter(|&n| keep.insert(n))
+                .collect();
+            frontier = next;
+        }
+        keep
+    }
+
+    // Graphviz source. Nodes are grouped into a cluster per dirname; the index is a double circle,
+    // categories folders, posts boxes, and placeholders for missing parents dashed notes. Link
+    // edges are dashed too. With `keep`, only those nodes (and the edges between them) are drawn.
+    pub fn dot(self: &Self, keep: Option<&HashSet<NodeIndex>>) -> String {
         static INDENT: &'static str = "    ";
-        let mut f = vec![];
-        f.push("digraph {{".to_string());
-        for ix in self.graph.node_indices() {
-            let name = match &self.graph[ix] {
-                PostNode::Node(n) => match n {
-                    PostTypes::Category(_) => format!("Category({})", n.name()),
-                    PostTypes::Post(_) => format!("Post({})", n.name()),
-                },
-                PostNode::Root() => "ROOT".to_string(),
+        let kept = |ix: NodeIndex| keep.map_or(true, |k| k.contains(&ix));
+        let mut clusters: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for ix in self.graph.node_indices().filter(|&ix| kept(ix)) {
+            let (dir, attrs) = match self.graph[ix] {
+                PostNode::Node(n) => {
+                    let label = if n.title().is_empty() { n.name() } else { n.title() };
+                    let shape = match n {
+                        _ if n.is_placeholder() => "note, style=dashed",
+                        PostTypes::Category(_) => "folder",
+                        PostTypes::Post(_) => "box",
+                    };
+                    (
+                        n.dirname(),
+                        format!(
+                            "label=\"{}\", tooltip=\"{}\", shape={}",
+                            dot_escape(&label),
+                            dot_escape(&n.names().join("\n")),
+                            shape
+                        ),
+                    )
+                }
+                PostNode::Root() => ("", "label=\"INDEX\", shape=doublecircle".to_string()),
             };
+            clusters
+                .entry(dir)
+                .or_insert_with(Vec::new)
+                .push(format!("n{} [{}];", ix.index(), attrs));
+        }
 
-            f.push(format!("{}{} [label=\"{}\"]", INDENT, ix.index(), name));
+        let mut f = vec![];
+        f.push("digraph {".to_string());
+        for (dir, nodes) in clusters {
+            if dir.is_empty() {
+                f.extend(nodes.into_iter().map(|n| format!("{}{}", INDENT, n)));
+            } else {
+                f.push(format!("{}subgraph \"cluster_{}\" {{", INDENT, dot_escape(dir)));
+                f.push(format!("{}{}label=\"{}\";", INDENT, INDENT, dot_escape(dir)));
+                f.extend(nodes.into_iter().map(|n| format!("{}{}{}", INDENT, INDENT, n)));
+                f.push(format!("{}}}", INDENT));
+            }
         }
 
         for edge in self.graph.edge_references() {
+            if !kept(edge.source()) || !kept(edge.target()) {
+                continue;
+            }
+            let style = match edge.weight() {
+                EdgeType::Parent => "",
+                EdgeType::Link => " [style=dashed, color=gray]",
+            };
             f.push(format!(
-                "{}{} -> {}",
+                "{}n{} -> n{}{};",
                 INDENT,
                 self.graph.to_index(edge.source()),
-                self.graph.to_index(edge.target())
+                self.graph.to_index(edge.target()),
+                style
             ));
         }
-        f.push("}}".to_string());
+        f.push("}".to_string());
         f.join("\n")
     }
 }
+
+// How a name written in a post (a parent, or a [[link]]) is found, from that post's dirname:
+// "/Category/Mecha" is a full name from the top, and "../Artists/X" (or "./X") is relative to the
+// dirname. Anything else is looked for in the dirname, then as a full name, and nowhere else, so
+// a post in another dirname is never picked up just because it has the same name.
+pub fn lookup<T: Copy>(names: &HashMap<String, T>, name: &str, dir: &str) -> Option<T> {
+    if is_path(name) {
+        return full_name(name, dir).and_then(|n| names.get(&n).cloned());
+    }
+    names
+        .get(&format!("{}/{}", dir, name))
+        .or_else(|| names.get(name))
+        .cloned()
+}
+
+// The full name a reference from `dir` stands for, whether or not it exists: paths are resolved
+// against the dirname, and other names are taken as they are. None when a path climbs out of the top.
+pub fn full_name(name: &str, dir: &str) -> Option<String> {
+    if !is_path(name) {
+        return Some(name.to_string());
+    }
+    let (mut parts, rest): (Vec<&str>, &str) = if name.starts_with('/') {
+        (vec![], &name[1..])
+    } else {
+        (dir.split('/').filter(|p| !p.is_empty()).collect(), name)
+    };
+    for part in rest.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop()?;
+            }
+            p => parts.push(p),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+// Names a missing reference was probably meant to be: the same but for case, or a few typos away
+// (from the whole name, or just its last part). Closest first, at most 3.
+pub fn suggest<T>(names: &HashMap<String, T>, name: &str, dir: &str) -> Vec<String> {
+    let wanted = full_name(name, dir).unwrap_or_else(|| name.to_string()).to_lowercase();
+    let close = cmp::max(1, wanted.chars().count() / 4);
+    let mut found: Vec<_> = names
+        .keys()
+        .filter_map(|n| {
+            let lower = n.to_lowercase();
+            let last = lower.rsplit('/').next().unwrap_or("");
+            let distance = cmp::min(levenshtein(&wanted, &lower), levenshtein(&wanted, last));
+            if distance <= close {
+                Some((distance, n))
+            } else {
+                None
+            }
+        }).collect();
+    found.sort_unstable();
+    found.into_iter().take(3).map(|(_, n)| n.to_string()).collect()
+}
+
+// The traversals behind `walk` and `shortest_path`, over any graph: `step` gives a node's
+// neighbours, in the order they should be visited. The template functions, which can't borrow
+// the Graph, run them over their own copy of it.
+pub fn breadth_first<N, F>(start: N, depth: Option<usize>, step: F) -> Vec<N>
+where
+    N: Copy + Eq + Hash,
+    F: Fn(N) -> Vec<N>,
+{
+    let mut seen: HashSet<_> = vec![start].into_iter().collect();
+    let mut out = vec![];
+    let mut frontier = vec![start];
+    let mut d = 0;
+    while !frontier.is_empty() && depth.map_or(true, |max| d < max) {
+        d += 1;
+        let mut next = vec![];
+        for ix in frontier {
+            next.extend(step(ix).into_iter().filter(|&n| seen.insert(n)));
+        }
+        out.extend(&next);
+        frontier = next;
+    }
+    out
+}
+
+pub fn shortest_chain<N, F>(from: N, to: N, step: F) -> Option<Vec<N>>
+where
+    N: Copy + Eq + Hash,
+    F: Fn(N) -> Vec<N>,
+{
+    let mut prev = HashMap::new();
+    prev.insert(from, from);
+    let mut frontier = vec![from];
+    while !frontier.is_empty() && !prev.contains_key(&to) {
+        let mut next = vec![];
+        for ix in frontier {
+            for n in step(ix) {
+                if !prev.contains_key(&n) {
+                    prev.insert(n, ix);
+                    next.push(n);
+                }
+            }
+        }
+        frontier = next;
+    }
+    if !prev.contains_key(&to) {
+        return None;
+    }
+    let mut path = vec![to];
+    while *path.last().unwrap() != from {
+        let last = *path.last().unwrap();
+        path.push(prev[&last]);
+    }
+    path.reverse();
+    Some(path)
+}
+
+fn is_path(name: &str) -> bool {
+    name.starts_with('/') || name.starts_with("./") || name.starts_with("../")
+}
+
+// contents of a double-quoted dot string
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -1,3 +1,4 @@
+use config::MarkdownOverrides;
 use errors;
 use errors::IOError::*;
 use quick_error::ResultExt;
@@ -6,9 +7,10 @@ use std::collections::HashSet;
 use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fmt;
 use serde::de::{Visitor, SeqAccess, value, Deserialize, Deserializer};
+use markdown::TocEntry;
 use toml;
 
 #[derive(Serialize, Debug, Default, Clone)]
@@ -17,6 +19,15 @@ pub struct TeraNextPost {
     pub title: String,
 }
 
+// A local image after processing; thumbnails are ordered by width, smallest first.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct TeraImage {
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub thumbnails: Vec<TeraImage>,
+}
+
 #[derive(Default, Debug, Deserialize, Serialize, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct Category {
@@ -25,16 +36,50 @@ pub struct Category {
     pub title: String,
     #[serde(default, deserialize_with = "string_or_vec", rename="parent")]
     pub parents: Vec<String>,
+    #[serde(default, deserialize_with = "string_or_vec", rename="not_parent")]
+    pub excluded_parents: Vec<String>, // never a child of these, even when they list it in `children`
     #[serde(default, deserialize_with = "string_or_vec", rename="alias")]
     pub aliases: Vec<String>,
     #[serde(default)]
     pub dirname: String, // associated directory
+    #[serde(default)]
+    pub image: String,
+    #[serde(skip_deserializing)]
+    pub img: RefCell<Option<TeraImage>>,
+    #[serde(default, deserialize_with = "string_or_vec", rename(deserialize="children"), skip_serializing)]
+    pub listed_children: Vec<String>, // children named here instead of by their own `parent`, in order
+    #[serde(default)]
+    pub transitive: bool, // list every post below this one, not just its children
+    #[serde(default)]
+    pub max_depth: Option<usize>, // how far below, when transitive
+    #[serde(default)]
+    pub template: Option<String>, // replaces the default template for this page
+    #[serde(default)]
+    pub markdown: MarkdownOverrides,
     #[serde(skip_deserializing)]
     pub body: String,
     #[serde(skip_deserializing)]
+    pub content: RefCell<String>, // body rendered to html
+    #[serde(skip_deserializing)]
+    pub toc: RefCell<Vec<TocEntry>>,
+    #[serde(default)]
+    pub summary: RefCell<String>, // plain text; derived from the body if not given
+    #[serde(skip_deserializing)]
     pub children: RefCell<Vec<TeraNextPost>>,
     #[serde(skip_deserializing)]
     pub parent_names: RefCell<Vec<TeraNextPost>>,
+    #[serde(skip_deserializing)]
+    pub backlinks: RefCell<Vec<TeraNextPost>>,
+    #[serde(skip_deserializing)]
+    pub related: RefCell<Vec<TeraNextPost>>, // most related posts first
+    #[serde(skip)]
+    pub bundle: Option<PathBuf>, // directory of a page bundle (`<dir>/index.toml`)
+    #[serde(skip_deserializing)]
+    pub placeholder: bool, // made up for a missing parent, rather than read from a file
+    #[serde(skip)]
+    pub file: PathBuf, // file the post was read from
+    #[serde(skip)]
+    pub body_line: usize, // line of that file the body starts on
 }
 
 #[derive(Default, Debug, Deserialize, Serialize, Clone)]
@@ -45,23 +90,49 @@ pub struct Post {
     pub title: String,
     #[serde(default, deserialize_with = "string_or_vec", rename="parent")]
     pub parents: Vec<String>,
+    #[serde(default, deserialize_with = "string_or_vec", rename="not_parent")]
+    pub excluded_parents: Vec<String>, // never a child of these, even when they list it in `children`
     #[serde(default, deserialize_with = "string_or_vec", rename="alias")]
     pub aliases: Vec<String>,
     #[serde(default)]
     pub dirname: String, // associated directory
     #[serde(default)]
     pub image: String,
+    #[serde(skip_deserializing)]
+    pub img: RefCell<Option<TeraImage>>,
     #[serde(default)]
     pub year: String,
     #[serde(default)]
     pub dl_url: String,
 
+    #[serde(default)]
+    pub template: Option<String>, // replaces the default template for this page
+    #[serde(default)]
+    pub markdown: MarkdownOverrides,
     #[serde(skip_deserializing)]
     pub body: String,
     #[serde(skip_deserializing)]
+    pub content: RefCell<String>, // body rendered to html
+    #[serde(skip_deserializing)]
+    pub toc: RefCell<Vec<TocEntry>>,
+    #[serde(default)]
+    pub summary: RefCell<String>, // plain text; derived from the body if not given
+    #[serde(skip_deserializing)]
     pub children: RefCell<Vec<TeraNextPost>>,
     #[serde(skip_deserializing)]
     pub parent_names: RefCell<Vec<TeraNextPost>>,
+    #[serde(skip_deserializing)]
+    pub backlinks: RefCell<Vec<TeraNextPost>>,
+    #[serde(skip_deserializing)]
+    pub related: RefCell<Vec<TeraNextPost>>, // most related posts first
+    #[serde(skip)]
+    pub bundle: Option<PathBuf>, // directory of a page bundle (`<dir>/index.toml`)
+    #[serde(skip_deserializing)]
+    pub placeholder: bool, // made up for a missing parent, rather than read from a file
+    #[serde(skip)]
+    pub file: PathBuf, // file the post was read from
+    #[serde(skip)]
+    pub body_line: usize, // line of that file the body starts on
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -91,6 +162,66 @@ impl PostTypes {
         }
     }
 
+    pub fn body(&self) -> &str {
+        match self {
+            PostTypes::Post(p) => &p.body,
+            PostTypes::Category(c) => &c.body,
+        }
+    }
+
+    pub fn bundle(&self) -> Option<&Path> {
+        match self {
+            PostTypes::Post(p) => p.bundle.as_ref().map(PathBuf::as_path),
+            PostTypes::Category(c) => c.bundle.as_ref().map(PathBuf::as_path),
+        }
+    }
+
+    pub fn file(&self) -> &Path {
+        match self {
+            PostTypes::Post(p) => &p.file,
+            PostTypes::Category(c) => &c.file,
+        }
+    }
+
+    pub fn body_line(&self) -> usize {
+        match self {
+            PostTypes::Post(p) => p.body_line,
+            PostTypes::Category(c) => c.body_line,
+        }
+    }
+
+    pub fn template(&self) -> Option<&str> {
+        match self {
+            PostTypes::Post(p) => p.template.as_ref().map(String::as_str),
+            PostTypes::Category(c) => c.template.as_ref().map(String::as_str),
+        }
+    }
+
+    pub fn dirname(&self) -> &str {
+        self.get_dir()
+    }
+
+    pub fn markdown(&self) -> &MarkdownOverrides {
+        match self {
+            PostTypes::Post(p) => &p.markdown,
+            PostTypes::Category(c) => &c.markdown,
+        }
+    }
+
+    pub fn image(&self) -> &str {
+        match self {
+            PostTypes::Post(p) => &p.image,
+            PostTypes::Category(c) => &c.image,
+        }
+    }
+
+    pub fn set_image(&self, img: TeraImage) {
+        match self {
+            PostTypes::Post(p) => *p.img.borrow_mut() = Some(img),
+            PostTypes::Category(c) => *c.img.borrow_mut() = Some(img),
+        }
+    }
+
     pub fn names(&self) -> Vec<String> {
         match self {
             PostTypes::Post(p) => {
@@ -119,12 +250,37 @@ impl PostTypes {
             format!("{}/{}", self.get_dir(), name)
         }
     }
+    pub fn aliases(&self) -> &Vec<String> {
+        match self {
+            PostTypes::Post(p) => &p.aliases,
+            PostTypes::Category(c) => &c.aliases,
+        }
+    }
+    pub fn is_placeholder(&self) -> bool {
+        match self {
+            PostTypes::Post(p) => p.placeholder,
+            PostTypes::Category(c) => c.placeholder,
+        }
+    }
     pub fn parents(&self) -> &Vec<String> {
         match self {
             PostTypes::Post(p) => &p.parents,
             PostTypes::Category(c) => &c.parents,
         }
     }
+    pub fn excluded_parents(&self) -> &[String] {
+        match self {
+            PostTypes::Post(p) => &p.excluded_parents,
+            PostTypes::Category(c) => &c.excluded_parents,
+        }
+    }
+    // only categories can list their children
+    pub fn listed_children(&self) -> &[String] {
+        match self {
+            PostTypes::Post(_) => &[],
+            PostTypes::Category(c) => &c.listed_children,
+        }
+    }
     pub fn set_children_names(&self, children: Vec<(String, String)>) {
         let chs: Vec<_> = children
             .iter()
@@ -149,9 +305,59 @@ impl PostTypes {
             PostTypes::Category(c) => c.parent_names.borrow_mut().extend(chs),
         }
     }
+    pub fn set_content(&self, content: String, toc: Vec<TocEntry>) {
+        match self {
+            PostTypes::Post(p) => {
+                *p.content.borrow_mut() = content;
+                *p.toc.borrow_mut() = toc;
+            }
+            PostTypes::Category(c) => {
+                *c.content.borrow_mut() = content;
+                *c.toc.borrow_mut() = toc;
+            }
+        }
+    }
+    pub fn summary(&self) -> String {
+        match self {
+            PostTypes::Post(p) => p.summary.borrow().to_string(),
+            PostTypes::Category(c) => c.summary.borrow().to_string(),
+        }
+    }
+    pub fn set_summary(&self, summary: String) {
+        match self {
+            PostTypes::Post(p) => *p.summary.borrow_mut() = summary,
+            PostTypes::Category(c) => *c.summary.borrow_mut() = summary,
+        }
+    }
+    pub fn set_backlinks(&self, backlinks: Vec<(String, String)>) {
+        let chs: Vec<_> = backlinks
+            .iter()
+            .map(|(path, title)| TeraNextPost {
+                path: path.to_string(),
+                title: title.to_string(),
+            }).collect();
+        match self {
+            PostTypes::Post(p) => p.backlinks.borrow_mut().extend(chs),
+            PostTypes::Category(c) => c.backlinks.borrow_mut().extend(chs),
+        }
+    }
+    pub fn set_related(&self, related: Vec<(String, String)>) {
+        let chs: Vec<_> = related
+            .iter()
+            .map(|(path, title)| TeraNextPost {
+                path: path.to_string(),
+                title: title.to_string(),
+            }).collect();
+        match self {
+            PostTypes::Post(p) => p.related.borrow_mut().extend(chs),
+            PostTypes::Category(c) => c.related.borrow_mut().extend(chs),
+        }
+    }
 }
 
-pub fn get_post(filepath: &PathBuf) -> Result<PostTypes, errors::IOError> {
+pub const BUNDLE_INDEX: &str = "index.toml";
+
+pub fn get_post(filepath: &PathBuf, postdir: &Path) -> Result<PostTypes, errors::IOError> {
     trace!("Parsing post {:?}", filepath);
     let mut f = File::open(filepath).context(filepath)?;
     let mut contents = String::new();
@@ -166,12 +372,24 @@ pub fn get_post(filepath: &PathBuf) -> Result<PostTypes, errors::IOError> {
         Some(s) => s.to_string(),
         None => String::new(),
     };
+    // the header's lines, then the `---` line
+    let body_line = tomlcfg.lines().count() + 2;
+
+    // a post named index.toml owns the rest of its directory; not the posts directory itself,
+    // or every other post's source would be published as its assets
+    let bundle = match (filepath.file_name(), filepath.parent()) {
+        (Some(f), Some(dir)) if f == BUNDLE_INDEX && dir != postdir => Some(dir.to_path_buf()),
+        _ => None,
+    };
 
     // add the body text; title defaults to name.
     match toml::from_str::<PostTypes>(&tomlcfg) {
         Ok(s) => match s {
             PostTypes::Post(mut p) => {
                 p.body = body;
+                p.bundle = bundle;
+                p.file = filepath.to_path_buf();
+                p.body_line = body_line;
                 p.title = match p.title.as_str() {
                     "" => p.name.clone(),
                     _ => p.title,
@@ -180,6 +398,9 @@ pub fn get_post(filepath: &PathBuf) -> Result<PostTypes, errors::IOError> {
             }
             PostTypes::Category(mut c) => {
                 c.body = body;
+                c.bundle = bundle;
+                c.file = filepath.to_path_buf();
+                c.body_line = body_line;
                 c.title = match c.title.as_str() {
                     "" => c.name.clone(),
                     _ => c.title,
@@ -231,7 +452,10 @@ fn find_files<F: Fn(&PathBuf) -> bool>(
 ) -> Result<(), errors::IOError> {
     for entry in fs::read_dir(dir).context(dir)? {
         let path = entry.context(dir)?.path();
-        if path.is_dir() {
+        // a page bundle; everything else in it is an asset of the post, not a post
+        if path.join(BUNDLE_INDEX).is_file() {
+            filepaths.push(path.join(BUNDLE_INDEX));
+        } else if path.is_dir() {
             find_files(&path, filepaths, filter)?;
         } else if filter(&path) {
             filepaths.push(path);
@@ -253,7 +477,7 @@ pub fn get_posts(postdir: &PathBuf) -> Result<Vec<PostTypes>, Vec<errors::IOErro
     info!("Found {} posts", filenames.len());
     let (posts, errors): (Vec<_>, Vec<_>) = filenames
         .iter()
-        .map(|ref f| get_post(&f.to_path_buf()))
+        .map(|ref f| get_post(&f.to_path_buf(), postdir))
         .partition(Result::is_ok);
 
     if errors.is_empty() {
@@ -284,17 +508,3 @@ pub fn get_posts(postdir: &PathBuf) -> Result<Vec<PostTypes>, Vec<errors::IOErro
         Err(errors.into_iter().map(Result::unwrap_err).collect())
     }
 }
-pub fn get_fakeposts(posts: &[PostTypes]) -> Vec<PostTypes> {
-    let names: HashSet<_> = posts.iter().flat_map(|item| item.names()).collect();
-
-    posts
-        .iter()
-        .flat_map(|item| item.parents())
-        .filter(|&p| !names.contains(p))
-        .map(|parent| {
-            PostTypes::Post(Post {
-                name: parent.clone(),
-                ..Default::default()
-            })
-        }).collect()
-}
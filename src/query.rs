@@ -0,0 +1,170 @@
+use clap::ArgMatches;
+use config::RouteConfig;
+use errors::QueryError;
+use petgraph::graph::NodeIndex;
+use post_graph::Graph;
+use serde_json;
+use serde_json::Value;
+
+// Answers a `query` subcommand from the graph, as text (one answer per line) or, with --json,
+// as a json document.
+pub fn run(graph: &Graph, args: &ArgMatches, limits: &RouteConfig) -> Result<String, QueryError> {
+    // --json can come before or after the question
+    let json = args.is_present("json") || args.subcommand().1.map_or(false, |q| q.is_present("json"));
+    let lookup = |name: &str| {
+        graph
+            .resolve_from(name, "")
+            .ok_or_else(|| QueryError::UnknownName(name.to_string(), graph.suggest(name, "")))
+    };
+    let names = |ixs: &[NodeIndex]| -> Vec<String> { ixs.iter().map(|&ix| graph.node_name(ix)).collect() };
+
+    let answer = match args.subcommand() {
+        ("routes", Some(q)) => {
+            let routes = graph.routes_to(lookup(q.value_of("name").unwrap())?, limits);
+            if routes.truncated {
+                warn!(
+                    "Only routes up to {} deep are listed, and no more than {}; see [routes] in the config",
+                    limits.max_depth, limits.max_routes
+                );
+            }
+            Answer::Routes(
+                routes
+                    .ends
+                    .iter()
+                    .map(|&end| {
+                        let mut route = names(&routes.nodes(end));
+                        route.reverse(); // found from the post up
+                        route
+                    }).collect(),
+            )
+        }
+        ("path", Some(q)) => {
+            let from = lookup(q.value_of("from").unwrap())?;
+            let to = lookup(q.value_of("to").unwrap())?;
+            Answer::Path(graph.shortest_path(from, to).map(|p| names(&p)))
+        }
+        ("ancestors", Some(q)) => {
+            let ix = lookup(q.value_of("name").unwrap())?;
+            Answer::Names(names(&graph.walk(ix, true, None)))
+        }
+        ("descendants", Some(q)) => {
+            let ix = lookup(q.value_of("name").unwrap())?;
+            let depth = match q.value_of("depth") {
+                Some(d) => Some(d.parse().map_err(|e| QueryError::InvalidDepth(d.to_string(), e))?),
+                None => None,
+            };
+            Answer::Names(names(&graph.walk(ix, false, depth)))
+        }
+        ("edge", Some(q)) => {
+            let (parent, child) = (q.value_of("parent").unwrap(), q.value_of("child").unwrap());
+            let declared = declared_by(graph, lookup(parent)?, lookup(child)?);
+            if declared.is_empty() {
+                return Err(QueryError::NoEdge(parent.to_string(), child.to_string()));
+            }
+            Answer::Edge(declared)
+        }
+        ("resolve", Some(q)) => {
+            let mut all: Vec<_> = match q.value_of("name") {
+                Some(name) => vec![(name.to_string(), lookup(name)?)],
+                None => graph.names().map(|(n, &ix)| (n.to_string(), ix)).collect(),
+            };
+            all.sort_unstable();
+            Answer::Resolve(
+                all.into_iter()
+                    .map(|(name, ix)| (name, graph.node_name(ix), file_of(graph, ix)))
+                    .collect(),
+            )
+        }
+        _ => unreachable!("clap requires a query"),
+    };
+    Ok(if json { answer.json() } else { answer.text() })
+}
+
+enum Answer {
+    Routes(Vec<Vec<String>>),
+    Path(Option<Vec<String>>),
+    Names(Vec<String>),
+    Edge(Vec<(String, String)>),            // (file, how it declares the relationship)
+    Resolve(Vec<(String, String, String)>), // (name, node, node's file)
+}
+
+impl Answer {
+    fn text(&self) -> String {
+        match self {
+            Answer::Routes(routes) => routes.iter().map(|r| r.join(" > ")).collect::<Vec<_>>().join("\n"),
+            Answer::Path(Some(path)) => path.join(" > "),
+            Answer::Path(None) => "no path".to_string(),
+            Answer::Names(names) => names.join("\n"),
+            Answer::Edge(declared) => declared
+                .iter()
+                .map(|(file, declaration)| format!("{}: {}", file, declaration))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Answer::Resolve(all) => all
+                .iter()
+                .map(|(name, node, file)| format!("{} -> {} ({})", name, node, file))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    fn json(&self) -> String {
+        let value = match self {
+            Answer::Routes(routes) => json!(routes),
+            Answer::Path(path) => json!(path),
+            Answer::Names(names) => json!(names),
+            Answer::Edge(declared) => Value::Array(
+                declared
+                    .iter()
+                    .map(|(file, declaration)| json!({"file": file, "declaration": declaration}))
+                    .collect(),
+            ),
+            Answer::Resolve(all) => Value::Array(
+                all.iter()
+                    .map(|(name, node, file)| json!({"name": name, "node": node, "file": file}))
+                    .collect(),
+            ),
+        };
+        serde_json::to_string_pretty(&value).unwrap()
+    }
+}
+
+fn file_of(graph: &Graph, ix: NodeIndex) -> String {
+    match graph.post(ix) {
+        Some(p) if p.is_placeholder() => "placeholder".to_string(),
+        Some(p) => p.file().display().to_string(),
+        None => "index".to_string(),
+    }
+}
+
+// Where a parent relationship comes from: the child's file, and each way it names the parent
+// (or that it names no parents at all, for the index), and the parent's file, if it lists the
+// child in `children`.
+fn declared_by(graph: &Graph, parent: NodeIndex, child: NodeIndex) -> Vec<(String, String)> {
+    if !graph.children(parent).contains(&child) {
+        return vec![];
+    }
+    let post = match graph.post(child) {
+        Some(p) => p,
+        None => return vec![],
+    };
+    let mut declared: Vec<_> = post
+        .parents()
+        .iter()
+        .filter(|p| graph.resolve_from(p, post.dirname()) == Some(parent))
+        .map(|p| (file_of(graph, child), format!("parent = {:?}", p)))
+        .collect();
+    if let Some(listing) = graph.post(parent) {
+        declared.extend(
+            listing
+                .listed_children()
+                .iter()
+                .filter(|c| graph.resolve_from(c, listing.dirname()) == Some(child))
+                .map(|c| (file_of(graph, parent), format!("children = [.., {:?}, ..]", c))),
+        );
+    }
+    if declared.is_empty() && post.parents().is_empty() {
+        declared.push((file_of(graph, child), "(no parents listed, so INDEX)".to_string()));
+    }
+    declared
+}
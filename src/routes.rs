@@ -0,0 +1,79 @@
+use petgraph::graph::NodeIndex;
+
+// A set of routes through the graph that share their prefixes: each step points back at the
+// step before it, so extending a route never copies it, and a route is just its last step.
+#[derive(Debug, Default)]
+pub struct Routes {
+    steps: Vec<Step>,
+    pub ends: Vec<usize>, // the complete routes, as their last step
+    pub truncated: bool,  // a limit was hit, so some routes are missing
+}
+
+#[derive(Debug)]
+struct Step {
+    node: NodeIndex,
+    prev: Option<usize>,
+    len: usize,            // edges taken so far
+    repeat: Option<usize>, // when `node` was already on the route: its position there
+}
+
+impl Routes {
+    pub fn new() -> Routes {
+        Routes::default()
+    }
+
+    pub fn push(&mut self, node: NodeIndex, prev: Option<usize>) -> usize {
+        let (len, repeat) = match prev {
+            Some(p) => (self.steps[p].len + 1, self.position(p, node)),
+            None => (0, None),
+        };
+        self.steps.push(Step {
+            node,
+            prev,
+            len,
+            repeat,
+        });
+        self.steps.len() - 1
+    }
+
+    pub fn step_count(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn node(&self, step: usize) -> NodeIndex {
+        self.steps[step].node
+    }
+
+    pub fn len(&self, step: usize) -> usize {
+        self.steps[step].len
+    }
+
+    // whether the route ending at `step` came back to a node it had already visited, and where
+    pub fn repeat(&self, step: usize) -> Option<usize> {
+        self.steps[step].repeat
+    }
+
+    // the nodes of the route ending at `step`, first to last
+    pub fn nodes(&self, step: usize) -> Vec<NodeIndex> {
+        let mut nodes = Vec::with_capacity(self.steps[step].len + 1);
+        let mut cur = Some(step);
+        while let Some(s) = cur {
+            nodes.push(self.steps[s].node);
+            cur = self.steps[s].prev;
+        }
+        nodes.reverse();
+        nodes
+    }
+
+    // position of `node` on the route ending at `step`, counting from its start
+    fn position(&self, step: usize, node: NodeIndex) -> Option<usize> {
+        let mut cur = Some(step);
+        while let Some(s) = cur {
+            if self.steps[s].node == node {
+                return Some(self.steps[s].len);
+            }
+            cur = self.steps[s].prev;
+        }
+        None
+    }
+}
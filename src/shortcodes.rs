@@ -0,0 +1,171 @@
+use links::code_ranges;
+use posts::PostTypes;
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use tera;
+use tera::{Context, Map, Number, Tera, Value};
+
+// shortcode templates live in the Tera instance under this prefix, ie `shortcodes/gallery.jinja2`
+const PREFIX: &str = "shortcodes/";
+
+lazy_static! {
+    // `{{ name(args) }}`
+    static ref SHORTCODE: Regex = Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\((.*?)\)\s*\}\}").unwrap();
+}
+
+pub fn add_shortcodes(tera: &mut Tera, dir: &Path) -> tera::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).map_err(|e| format!("Can't read {:?}: {}", dir, e))? {
+        let path = entry.map_err(|e| format!("Can't read {:?}: {}", dir, e))?.path();
+        if let Some(name) = path.file_name().and_then(|f| f.to_str()) {
+            if path.is_file() {
+                files.push((path.clone(), format!("{}{}", PREFIX, name)));
+            }
+        }
+    }
+    tera.add_template_files(
+        files
+            .iter()
+            .map(|(path, name)| (path, Some(name.as_str())))
+            .collect(),
+    )
+}
+
+// the template for a shortcode name, if one was loaded; names are the file stem
+fn find_template(tera: &Tera, name: &str) -> Option<String> {
+    tera.templates
+        .keys()
+        .filter(|t| t.starts_with(PREFIX))
+        .find(|t| {
+            let file = &t[PREFIX.len()..];
+            file == name || file.split('.').next() == Some(name)
+        }).cloned()
+}
+
+// Replace every `{{ name(key=value, ...) }}` whose name is a loaded shortcode with its rendered
+// template. The template sees its arguments as variables, plus the current `post` and `basepath`.
+// Anything that looks like a call but isn't a known shortcode, or is inside inline code or a code
+// block, is left alone.
+pub fn render_shortcodes(
+    body: &str,
+    tera: &Tera,
+    post: &PostTypes,
+    basepath: &str,
+) -> tera::Result<String> {
+    let code = code_ranges(body);
+    let mut out = String::with_capacity(body.len());
+    let mut last = 0;
+    for cap in SHORTCODE.captures_iter(body) {
+        let whole = cap.get(0).unwrap();
+        if code.iter().any(|r| r.start <= whole.start() && whole.start() < r.end) {
+            continue;
+        }
+        let name = &cap[1];
+        let template = match find_template(tera, name) {
+            Some(t) => t,
+            None => continue,
+        };
+        let line = post.body_line() + body[..whole.start()].matches('\n').count();
+        let location = format!("{:?}:{}: shortcode `{}`", post.file(), line, name);
+
+        let args = parse_args(&cap[2]).map_err(|e| format!("{}: {}", location, e))?;
+        let mut ctx = Context::new();
+        for (k, v) in &args {
+            ctx.insert(k, v);
+        }
+        ctx.insert("post", post);
+        ctx.insert("basepath", &basepath);
+        let html = tera
+            .render(&template, &ctx)
+            .map_err(|e| tera::Error::with_chain(e, location))?;
+
+        out.push_str(&body[last..whole.start()]);
+        out.push_str(html.trim());
+        last = whole.end();
+    }
+    out.push_str(&body[last..]);
+    Ok(out)
+}
+
+// key=value pairs, comma separated; values are "strings", 'strings', numbers, true or false
+fn parse_args(s: &str) -> Result<Map<String, Value>, String> {
+    let mut args = Map::new();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let eq = rest
+            .find('=')
+            .ok_or_else(|| format!("expected key=value, found {:?}", rest))?;
+        let key = rest[..eq].trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(format!("invalid argument name {:?}", key));
+        }
+        rest = rest[eq + 1..].trim_start();
+        let (value, remaining) = parse_value(rest)?;
+        args.insert(key.to_string(), value);
+        rest = remaining.trim_start();
+        if rest.starts_with(',') {
+            rest = rest[1..].trim_start();
+        } else if !rest.is_empty() {
+            return Err(format!("expected `,` before {:?}", rest));
+        }
+    }
+    Ok(args)
+}
+
+fn parse_value(s: &str) -> Result<(Value, &str), String> {
+    if s.starts_with('"') || s.starts_with('\'') {
+        let quote = s.chars().next().unwrap();
+        let mut value = String::new();
+        let mut escaped = false;
+        for (i, c) in s.char_indices().skip(1) {
+            match c {
+                _ if escaped => {
+                    value.push(c);
+                    escaped = false;
+                }
+                '\\' => escaped = true,
+                c if c == quote => return Ok((Value::String(value), &s[i + 1..])),
+                c => value.push(c),
+            }
+        }
+        return Err(format!("unterminated string {}", s));
+    }
+    let end = s.find(',').unwrap_or_else(|| s.len());
+    let token = s[..end].trim();
+    let value = match token {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        t => {
+            if let Ok(i) = t.parse::<i64>() {
+                Value::Number(i.into())
+            } else if let Some(n) = t.parse::<f64>().ok().and_then(Number::from_f64) {
+                Value::Number(n)
+            } else {
+                return Err(format!("invalid value {:?}; strings must be quoted", t));
+            }
+        }
+    };
+    Ok((value, &s[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use posts::Post;
+
+    #[test]
+    fn shortcodes_in_code_are_shown_as_written() {
+        let mut tera = Tera::default();
+        tera.add_raw_template("shortcodes/hi.jinja2", "<b>{{ who }}</b>").unwrap();
+        let post = PostTypes::Post(Post::default());
+        let body = "{{ hi(who=\"a\") }}\n\n```\n{{ hi(who=\"b\") }}\n```\n\nand `{{ hi(who=\"c\") }}`\n";
+        assert_eq!(
+            render_shortcodes(body, &tera, &post, "").unwrap(),
+            "<b>a</b>\n\n```\n{{ hi(who=\"b\") }}\n```\n\nand `{{ hi(who=\"c\") }}`\n"
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use errors;
+use markdown::escape;
+use petgraph;
+use petgraph::algo::tarjan_scc;
+use post_graph::{Graph, PostNode};
+use posts::PostTypes;
+use quick_error::ResultExt;
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// layout, in px
+const NODE_W: usize = 150;
+const NODE_H: usize = 28;
+const COL_W: usize = 170;
+const ROW_H: usize = 80;
+const MARGIN: usize = 20;
+const LABEL_CHARS: usize = 20;
+
+// numbers each svg's arrow marker, since several minimaps can end up inline on one page
+static SVG_ID: AtomicUsize = AtomicUsize::new(0);
+
+const STYLE: &str = "
+.node rect { stroke: #333; stroke-width: 1 }
+.node text { font: 12px sans-serif; text-anchor: middle; dominant-baseline: central }
+.node.index rect { fill: #333 } .node.index text { fill: white }
+.node.category rect { fill: #fdd }
+.node.post rect { fill: white }
+.node.current rect { stroke-width: 3 }
+.edge { fill: none; stroke: #999 }
+.edge.cycle { stroke: red; stroke-dasharray: 4 2 }
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    Index,
+    Category,
+    Post,
+}
+
+#[derive(Debug, Clone)]
+pub struct MapNode {
+    pub title: String,
+    pub url: String,
+    pub kind: NodeKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MapEdge {
+    pub parent: usize,
+    pub child: usize,
+    pub cycle: bool, // part of a cycle of parent relationships
+}
+
+// The site structure (parent relationships only), ready to be drawn. Node i is the graph's
+// node with index i.
+#[derive(Debug, Clone)]
+pub struct Map {
+    pub nodes: Vec<MapNode>,
+    pub edges: Vec<MapEdge>,
+    pub root: usize,
+}
+
+impl Map {
+    pub fn from_graph(graph: &Graph, basepath: &str) -> Map {
+        let g = &graph.graph;
+        let nodes = g
+            .node_indices()
+            .map(|ix| match g[ix] {
+                PostNode::Node(p) => MapNode {
+                    title: if p.title().is_empty() { p.name() } else { p.title() },
+                    url: graph.url_for(ix, basepath),
+                    kind: match p {
+                        PostTypes::Post(_) => NodeKind::Post,
+                        PostTypes::Category(_) => NodeKind::Category,
+                    },
+                },
+                PostNode::Root() => MapNode {
+                    title: "index".to_string(),
+                    url: graph.url_for(ix, basepath),
+                    kind: NodeKind::Index,
+                },
+            }).collect();
+
+        // an edge is part of a cycle when both ends are in the same strongly connected component
+        let mut parents_only = petgraph::Graph::<(), ()>::new();
+        for _ in g.node_indices() {
+            parents_only.add_node(());
+        }
+        let mut pairs = vec![];
+        for ix in g.node_indices() {
+            for child in graph.children(ix) {
+                parents_only.add_edge(ix, child, ());
+                pairs.push((ix.index(), child.index()));
+            }
+        }
+        let mut component = HashMap::new();
+        for (i, scc) in tarjan_scc(&parents_only).into_iter().enumerate() {
+            if scc.len() > 1 {
+                for ix in scc {
+                    component.insert(ix.index(), i);
+                }
+            }
+        }
+        pairs.sort_unstable();
+        pairs.dedup();
+        let edges = pairs
+            .into_iter()
+            .map(|(parent, child)| MapEdge {
+                parent,
+                child,
+                cycle: component.contains_key(&parent) && component.get(&parent) == component.get(&child),
+            }).collect();
+        Map {
+            nodes,
+            edges,
+            root: graph.root.index(),
+        }
+    }
+
+    // every node's neighbours, in edge order, with the row they sit in relative to it: a child
+    // is one row down (+1), a parent one up (-1)
+    fn adjacency(&self) -> Vec<Vec<(usize, i64)>> {
+        let mut adjacent = vec![vec![]; self.nodes.len()];
+        for e in &self.edges {
+            adjacent[e.parent].push((e.child, 1));
+            adjacent[e.child].push((e.parent, -1));
+        }
+        adjacent
+    }
+
+    // Everything within `depth` steps of a node, following relationships either way; `center`
+    // becomes the new root. Node indices are renumbered.
+    pub fn neighbourhood(&self, center: usize, depth: usize) -> Map {
+        let adjacent = self.adjacency();
+        let mut keep = vec![center];
+        let mut seen: HashSet<_> = keep.iter().cloned().collect();
+        let mut frontier = keep.clone();
+        for _ in 0..depth {
+            let mut next = vec![];
+            for &ix in &frontier {
+                for &(n, _) in &adjacent[ix] {
+                    if seen.insert(n) {
+                        next.push(n);
+                    }
+                }
+            }
+            keep.extend(&next);
+            frontier = next;
+        }
+        let renumber: HashMap<_, _> = keep.iter().enumerate().map(|(new, &old)| (old, new)).collect();
+        Map {
+            nodes: keep.iter().map(|&ix| self.nodes[ix].clone()).collect(),
+            edges: self
+                .edges
+                .iter()
+                .filter(|e| renumber.contains_key(&e.parent) && renumber.contains_key(&e.child))
+                .map(|e| MapEdge {
+                    parent: renumber[&e.parent],
+                    child: renumber[&e.child],
+                    cycle: e.cycle,
+                }).collect(),
+            root: 0,
+        }
+    }
+
+    // Layers come from a walk out from the root: a child goes one row below the node it was
+    // reached from, a parent one row above. Within a row, nodes are ordered to sit near their
+    // neighbours in the rows around them.
+    fn layout(&self) -> Vec<(usize, usize)> {
+        let n = self.nodes.len();
+        let adjacent = self.adjacency();
+        let mut layer: Vec<Option<i64>> = vec![None; n];
+        let starts = ::std::iter::once(self.root).chain(0..n);
+        for start in starts {
+            if layer[start].is_some() {
+                continue;
+            }
+            layer[start] = Some(0);
+            let mut queue: VecDeque<_> = vec![start].into_iter().collect();
+            while let Some(ix) = queue.pop_front() {
+                let l = layer[ix].unwrap();
+                for &(next, step) in &adjacent[ix] {
+                    if layer[next].is_none() {
+                        layer[next] = Some(l + step);
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        let min = layer.iter().filter_map(|&l| l).min().unwrap_or(0);
+        let layer: Vec<usize> = layer.into_iter().map(|l| (l.unwrap() - min) as usize).collect();
+
+        let mut rows: Vec<Vec<usize>> = vec![vec![]; layer.iter().max().map_or(0, |m| m + 1)];
+        for ix in 0..n {
+            rows[layer[ix]].push(ix);
+        }
+        for row in &mut rows {
+            row.sort_by(|&a, &b| self.nodes[a].title.cmp(&self.nodes[b].title).then(a.cmp(&b)));
+        }
+        // a couple of sweeps down and back up, placing each node at the mean position of its
+        // neighbours in the row it's being compared to
+        let mut pos = vec![0.0; n];
+        for sweep in 0..4 {
+            for row in &rows {
+                for (i, &ix) in row.iter().enumerate() {
+                    pos[ix] = i as f64;
+                }
+            }
+            let order: Vec<usize> = if sweep % 2 == 0 {
+                (1..rows.len()).collect()
+            } else {
+                (0..rows.len().saturating_sub(1)).rev().collect()
+            };
+            for r in order {
+                let neighbour_row = if sweep % 2 == 0 { r - 1 } else { r + 1 };
+                let mut keyed: Vec<_> = rows[r]
+                    .iter()
+                    .map(|&ix| {
+                        let near: Vec<f64> = adjacent[ix]
+                            .iter()
+                            .map(|&(o, _)| o)
+                            .filter(|&o| layer[o] == neighbour_row)
+                            .map(|o| pos[o])
+                            .collect();
+                        let key = if near.is_empty() {
+                            pos[ix]
+                        } else {
+                            near.iter().sum::<f64>() / near.len() as f64
+                        };
+                        (key, ix)
+                    }).collect();
+                keyed.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                rows[r] = keyed.into_iter().map(|(_, ix)| ix).collect();
+                for (i, &ix) in rows[r].iter().enumerate() {
+                    pos[ix] = i as f64;
+                }
+            }
+        }
+
+        let widest = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut coords = vec![(0, 0); n];
+        for (r, row) in rows.iter().enumerate() {
+            let offset = (widest - row.len()) * COL_W / 2;
+            for (i, &ix) in row.iter().enumerate() {
+                coords[ix] = (
+                    MARGIN + offset + i * COL_W + NODE_W / 2,
+                    MARGIN + r * ROW_H + NODE_H / 2,
+                );
+            }
+        }
+        coords
+    }
+
+    // A standalone svg; every node links to its page. `current` is drawn with a heavier border.
+    pub fn to_svg(&self, current: Option<usize>) -> String {
+        let coords = self.layout();
+        let arrow = format!("arrow-{}", SVG_ID.fetch_add(1, Ordering::Relaxed));
+        let width = coords.iter().map(|c| c.0).max().unwrap_or(0) + NODE_W / 2 + MARGIN;
+        let height = coords.iter().map(|c| c.1).max().unwrap_or(0) + NODE_H / 2 + MARGIN;
+        let mut svg = String::new();
+        let _ = write!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" class=\"sitemap\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n\
+             <style>{style}</style>\n\
+             <defs><marker id=\"{arrow}\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\">\
+             <path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"#999\"/></marker></defs>\n",
+            w = width,
+            h = height,
+            style = STYLE,
+            arrow = arrow
+        );
+        for e in &self.edges {
+            let ((px, py), (cx, cy)) = (coords[e.parent], coords[e.child]);
+            let class = if e.cycle { "edge cycle" } else { "edge" };
+            let path = if cy > py {
+                format!("M {} {} L {} {}", px, py + NODE_H / 2, cx, cy - NODE_H / 2)
+            } else {
+                // pointing up or sideways: bow out to the right so it doesn't run through nodes
+                let bend = cmp::max(px, cx) + NODE_W / 2 + MARGIN;
+                format!(
+                    "M {} {} C {} {} {} {} {} {}",
+                    px + NODE_W / 2,
+                    py,
+                    bend,
+                    py,
+                    bend,
+                    cy,
+                    cx + NODE_W / 2,
+                    cy
+                )
+            };
+            let _ = writeln!(
+                svg,
+                "<path class=\"{}\" d=\"{}\" marker-end=\"url(#{})\"/>",
+                class, path, arrow
+            );
+        }
+        for (ix, node) in self.nodes.iter().enumerate() {
+            let (x, y) = coords[ix];
+            let kind = match node.kind {
+                NodeKind::Index => "index",
+                NodeKind::Category => "category",
+                NodeKind::Post => "post",
+            };
+            let current = if current == Some(ix) { " current" } else { "" };
+            let _ = writeln!(
+                svg,
+                "<a href=\"{url}\"><g class=\"node {kind}{current}\"><title>{title}</title>\
+                 <rect x=\"{x}\" y=\"{y}\" width=\"{w}\" height=\"{h}\" rx=\"{rx}\"/>\
+                 <text x=\"{tx}\" y=\"{ty}\">{label}</text></g></a>",
+                url = escape(&node.url),
+                kind = kind,
+                current = current,
+                title = escape(&node.title),
+                x = x - NODE_W / 2,
+                y = y - NODE_H / 2,
+                w = NODE_W,
+                h = NODE_H,
+                rx = if node.kind == NodeKind::Category { NODE_H / 2 } else { 0 },
+                tx = x,
+                ty = y,
+                label = escape(&truncate(&node.title, LABEL_CHARS))
+            );
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+fn truncate(s: &str, chars: usize) -> String {
+    if s.chars().count() > chars {
+        format!("{}…", s.chars().take(chars - 1).collect::<String>())
+    } else {
+        s.to_string()
+    }
+}
+
+pub fn write_svg(wwwdir: &Path, svg: &str) -> Result<(), errors::IOError> {
+    let target = wwwdir.join("sitemap.svg");
+    fs::write(&target, svg).context(target.as_path())?;
+    Ok(())
+}